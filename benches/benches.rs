@@ -23,7 +23,7 @@ fn bench_usr(b: &mut Bencher) {
         .map(|e| OsString::from(e.unwrap().path().as_os_str()))
         .collect::<Vec<_>>();
     b.iter(|| {
-        black_box(ldd(&files, false, &tmpdir));
+        black_box(ldd(&files, &tmpdir));
     });
 }
 
@@ -0,0 +1,69 @@
+use std::ffi::{CStr, OsStr, OsString};
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+
+use kmod_sys::{self, kmod_config_iter};
+use log::trace;
+
+use crate::errors::{ErrorKind, Result};
+
+/// Iterator over one kind of the `modprobe.d` configuration of a context, the module
+/// name or alias and the rest of the line
+///
+/// Blacklist entries have an empty value.
+pub struct ConfigIterator {
+    iter: *mut kmod_config_iter,
+}
+
+impl Drop for ConfigIterator {
+    fn drop(&mut self) {
+        trace!("dropping kmod_config_iter: {:?}", self.iter);
+        unsafe { kmod_sys::kmod_config_iter_free_iter(self.iter) };
+    }
+}
+
+impl ConfigIterator {
+    #[inline]
+    pub(crate) fn new(iter: *mut kmod_config_iter) -> Result<ConfigIterator> {
+        if iter.is_null() {
+            Err(ErrorKind::Errno(errno::errno()).into())
+        } else {
+            trace!("creating kmod_config_iter: {:?}", iter);
+            Ok(ConfigIterator { iter })
+        }
+    }
+}
+
+impl Iterator for ConfigIterator {
+    type Item = (String, OsString);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        // the iterator starts before the first entry
+        if !unsafe { kmod_sys::kmod_config_iter_next(self.iter) } {
+            return None;
+        }
+
+        let key = unsafe { kmod_sys::kmod_config_iter_get_key(self.iter) };
+        let value = unsafe { kmod_sys::kmod_config_iter_get_value(self.iter) };
+
+        if key.is_null() {
+            panic!("Empty config key");
+        }
+
+        let key = unsafe { CStr::from_ptr(key) }
+            .to_string_lossy()
+            .into_owned();
+        let value = unsafe { value.as_ref().map(|ptr| CStr::from_ptr(ptr)) }
+            .map(|v| OsStr::from_bytes(v.to_bytes()).to_os_string())
+            .unwrap_or_default();
+
+        Some((key, value))
+    }
+}
+
+impl fmt::Debug for ConfigIterator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("ConfigIterator { .. }")
+    }
+}
@@ -5,6 +5,7 @@ use std::{fmt, ptr};
 use chainerror::prelude::v1::*;
 use log::trace;
 
+use crate::config::ConfigIterator;
 use crate::errors::{ErrorKind, Result};
 use crate::modules::{Module, ModuleIterator};
 
@@ -211,6 +212,78 @@ impl Context {
         let dirname = unsafe { CStr::from_ptr(dirname) };
         OsString::from_vec(dirname.to_bytes().to_vec())
     }
+
+    /// Get the modules blacklisted in the configuration.
+    ///
+    /// ```
+    /// let ctx = kmod::Context::new().unwrap();
+    /// let blacklisted: Vec<_> = ctx.config_blacklists().unwrap().map(|(m, _)| m).collect();
+    /// ```
+    pub fn config_blacklists(&self) -> Result<ConfigIterator> {
+        ConfigIterator::new(unsafe { kmod_sys::kmod_config_get_blacklists(self.ctx) })
+    }
+
+    /// Get the `install` commands of the configuration, by module name.
+    ///
+    /// ```
+    /// let ctx = kmod::Context::new().unwrap();
+    /// for (module, command) in ctx.config_install_commands().unwrap() {
+    ///     // ...
+    /// }
+    /// ```
+    pub fn config_install_commands(&self) -> Result<ConfigIterator> {
+        ConfigIterator::new(unsafe { kmod_sys::kmod_config_get_install_commands(self.ctx) })
+    }
+
+    /// Get the `remove` commands of the configuration, by module name.
+    ///
+    /// ```
+    /// let ctx = kmod::Context::new().unwrap();
+    /// for (module, command) in ctx.config_remove_commands().unwrap() {
+    ///     // ...
+    /// }
+    /// ```
+    pub fn config_remove_commands(&self) -> Result<ConfigIterator> {
+        ConfigIterator::new(unsafe { kmod_sys::kmod_config_get_remove_commands(self.ctx) })
+    }
+
+    /// Get the aliases of the configuration with their module names.
+    ///
+    /// ```
+    /// let ctx = kmod::Context::new().unwrap();
+    /// for (alias, module) in ctx.config_aliases().unwrap() {
+    ///     // ...
+    /// }
+    /// ```
+    pub fn config_aliases(&self) -> Result<ConfigIterator> {
+        ConfigIterator::new(unsafe { kmod_sys::kmod_config_get_aliases(self.ctx) })
+    }
+
+    /// Get the module options of the configuration, by module name.
+    ///
+    /// ```
+    /// let ctx = kmod::Context::new().unwrap();
+    /// for (module, options) in ctx.config_options().unwrap() {
+    ///     // ...
+    /// }
+    /// ```
+    pub fn config_options(&self) -> Result<ConfigIterator> {
+        ConfigIterator::new(unsafe { kmod_sys::kmod_config_get_options(self.ctx) })
+    }
+
+    /// Get the soft dependencies of the configuration, by module name.
+    ///
+    /// The value is the rest of the `softdep` line, like `pre: foo post: bar`.
+    ///
+    /// ```
+    /// let ctx = kmod::Context::new().unwrap();
+    /// for (module, softdeps) in ctx.config_softdeps().unwrap() {
+    ///     // ...
+    /// }
+    /// ```
+    pub fn config_softdeps(&self) -> Result<ConfigIterator> {
+        ConfigIterator::new(unsafe { kmod_sys::kmod_config_get_softdeps(self.ctx) })
+    }
 }
 
 impl fmt::Debug for Context {
@@ -41,6 +41,7 @@
     rust_2018_compatibility
 )]
 
+pub use config::ConfigIterator;
 pub use ctx::*;
 pub use errno::Errno;
 pub use errors::{Error, ErrorKind, Result};
@@ -74,6 +75,7 @@ mod errors {
     }
 }
 
+mod config;
 mod ctx;
 mod modules;
 
@@ -99,6 +101,66 @@ mod tests {
         }
     }
 
+    #[test]
+    fn modinfo() {
+        let ctx = Context::new().unwrap();
+        // the modules of the running kernel are below /lib/modules/<release>
+        let dirname = ctx.dirname();
+        let release = std::path::Path::new(&dirname)
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+
+        let mut checked = 0;
+        for module in ctx.modules_loaded().unwrap() {
+            // builtin modules have no file to read the info from
+            if module.path().is_none() {
+                continue;
+            }
+            let name = module.name().unwrap_or_default().to_string_lossy();
+
+            let vermagic = module.vermagic().unwrap().unwrap_or_default();
+            assert!(
+                vermagic.starts_with(&format!("{} ", release)),
+                "{}: vermagic {:?}",
+                name,
+                vermagic
+            );
+            assert!(!module.license().unwrap().unwrap_or_default().is_empty());
+            assert_eq!(
+                module.info_value("vermagic").unwrap(),
+                Some(vermagic.into())
+            );
+
+            // empty without CONFIG_MODVERSIONS
+            let versions = module.versions().unwrap().collect::<Vec<_>>();
+            if !versions.is_empty() {
+                assert!(versions.iter().all(|(symbol, _)| !symbol.is_empty()));
+                assert!(versions.iter().any(|(_, crc)| *crc != 0));
+            }
+            checked += 1;
+        }
+        assert!(checked > 0);
+    }
+
+    #[test]
+    fn config() {
+        let ctx = Context::new().unwrap();
+
+        for (name, _) in ctx.config_blacklists().unwrap() {
+            println!("blacklist {}", name);
+        }
+        for (name, options) in ctx.config_options().unwrap() {
+            assert!(!name.is_empty());
+            println!("options {} {:?}", name, options);
+        }
+        let _ = ctx.config_install_commands().unwrap().count();
+        let _ = ctx.config_remove_commands().unwrap().count();
+        let _ = ctx.config_aliases().unwrap().count();
+        let _ = ctx.config_softdeps().unwrap().count();
+    }
+
     #[test]
     fn bad_name() {
         let ctx = Context::new().unwrap();
@@ -1,5 +1,6 @@
-use std::ffi::{CStr, CString, OsStr};
+use std::ffi::{CStr, CString, OsStr, OsString};
 use std::fmt;
+use std::os::raw::{c_char, c_int};
 use std::os::unix::ffi::OsStrExt;
 
 use chainerror::*;
@@ -8,6 +9,23 @@ use log::trace;
 
 use crate::errors::{ErrorKind, Result};
 
+type GetWeakdeps = unsafe extern "C" fn(*const kmod_module, *mut *mut kmod_list) -> c_int;
+
+/// `kmod_module_get_weakdeps()`, which only libkmod 33 and later provide
+fn kmod_module_get_weakdeps() -> Option<GetWeakdeps> {
+    let sym = unsafe {
+        libc::dlsym(
+            libc::RTLD_DEFAULT,
+            b"kmod_module_get_weakdeps\0".as_ptr() as *const c_char,
+        )
+    };
+    if sym.is_null() {
+        None
+    } else {
+        Some(unsafe { std::mem::transmute::<*mut libc::c_void, GetWeakdeps>(sym) })
+    }
+}
+
 /// Wrapper around a kmod_module
 pub struct Module {
     inner: *mut kmod_module,
@@ -81,6 +99,28 @@ impl Module {
         }
     }
 
+    /// Get this modules weak dependencies
+    ///
+    /// A weak dependency may be loaded by the module at runtime, but is not loaded
+    /// along with it. libkmod reports the `weakdep` of the configuration and the
+    /// `weakdep=` of the module since version 33. With an older libkmod `Ok(None)`
+    /// is returned, see [`Module::weakdeps`].
+    pub fn weak_dependencies(&self) -> Result<Option<ModuleIterator>> {
+        let get_weakdeps = match kmod_module_get_weakdeps() {
+            Some(f) => f,
+            None => return Ok(None),
+        };
+        let mut weak: *mut kmod_list = std::ptr::null_mut();
+
+        let ret = unsafe { get_weakdeps(self.inner, &mut weak) };
+
+        if ret < 0 {
+            Err(ErrorKind::Errno(errno::errno()).into())
+        } else {
+            Ok(Some(ModuleIterator::new(weak)))
+        }
+    }
+
     /// Get this modules dependencies
     #[inline]
     pub fn dependency_symbols(&self) -> Result<SymbolIterator> {
@@ -95,6 +135,129 @@ impl Module {
         }
     }
 
+    /// Iterate over the key/value pairs of the `.modinfo` section
+    #[inline]
+    pub fn info(&self) -> Result<InfoIterator> {
+        let mut info: *mut kmod_list = std::ptr::null_mut();
+
+        let ret = unsafe { kmod_sys::kmod_module_get_info(self.inner, &mut info) };
+        if ret < 0 {
+            Err(ErrorKind::Errno(errno::errno()).into())
+        } else {
+            Ok(InfoIterator::new(info))
+        }
+    }
+
+    /// Get all `.modinfo` values of `key`
+    pub fn info_values(&self, key: &str) -> Result<Vec<OsString>> {
+        Ok(self
+            .info()?
+            .filter(|(k, _)| k == key)
+            .map(|(_, v)| v)
+            .collect())
+    }
+
+    /// Get the first `.modinfo` value of `key`
+    pub fn info_value(&self, key: &str) -> Result<Option<OsString>> {
+        Ok(self.info()?.find(|(k, _)| k == key).map(|(_, v)| v))
+    }
+
+    /// Get the firmware files the module may load
+    #[inline]
+    pub fn firmware(&self) -> Result<Vec<OsString>> {
+        self.info_values("firmware")
+    }
+
+    /// Get the aliases of the module
+    #[inline]
+    pub fn aliases(&self) -> Result<Vec<OsString>> {
+        self.info_values("alias")
+    }
+
+    /// Get the names of the modules this module depends on, from `depends=`
+    pub fn depends(&self) -> Result<Vec<String>> {
+        Ok(self
+            .info_value("depends")?
+            .map(|v| {
+                v.to_string_lossy()
+                    .split(',')
+                    .filter(|d| !d.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// Get the `softdep=` lines of the module, like `pre: crc32c post: foo`
+    #[inline]
+    pub fn softdeps(&self) -> Result<Vec<String>> {
+        Ok(self
+            .info_values("softdep")?
+            .iter()
+            .map(|v| v.to_string_lossy().into_owned())
+            .collect())
+    }
+
+    /// Get the names of the `weakdep=` entries of the module
+    #[inline]
+    pub fn weakdeps(&self) -> Result<Vec<String>> {
+        Ok(self
+            .info_values("weakdep")?
+            .iter()
+            .map(|v| v.to_string_lossy().trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect())
+    }
+
+    /// Get the license of the module
+    #[inline]
+    pub fn license(&self) -> Result<Option<String>> {
+        self.info_string("license")
+    }
+
+    /// Get the kernel version magic the module was built for
+    #[inline]
+    pub fn vermagic(&self) -> Result<Option<String>> {
+        self.info_string("vermagic")
+    }
+
+    /// Get the signer of a signed module
+    #[inline]
+    pub fn signer(&self) -> Result<Option<String>> {
+        self.info_string("signer")
+    }
+
+    /// Get the key id of the signature of a signed module
+    #[inline]
+    pub fn sig_key(&self) -> Result<Option<String>> {
+        self.info_string("sig_key")
+    }
+
+    /// Get the hash algorithm of the signature of a signed module
+    #[inline]
+    pub fn sig_hashalgo(&self) -> Result<Option<String>> {
+        self.info_string("sig_hashalgo")
+    }
+
+    fn info_string(&self, key: &str) -> Result<Option<String>> {
+        Ok(self
+            .info_value(key)?
+            .map(|v| v.to_string_lossy().into_owned()))
+    }
+
+    /// Iterate over the symbol versions (`__versions`) of the module
+    #[inline]
+    pub fn versions(&self) -> Result<VersionIterator> {
+        let mut versions: *mut kmod_list = std::ptr::null_mut();
+
+        let ret = unsafe { kmod_sys::kmod_module_get_versions(self.inner, &mut versions) };
+        if ret < 0 {
+            Err(ErrorKind::Errno(errno::errno()).into())
+        } else {
+            Ok(VersionIterator::new(versions))
+        }
+    }
+
     #[inline]
     pub fn path(&self) -> Option<&OsStr> {
         unsafe {
@@ -251,3 +414,119 @@ impl fmt::Debug for SymbolIterator {
         f.pad("SymbolIterator { .. }")
     }
 }
+
+/// Iterator over a kmod_list of `.modinfo` key/value pairs
+pub struct InfoIterator {
+    list: *mut kmod_list,
+    iter: *mut kmod_list,
+}
+
+impl Drop for InfoIterator {
+    fn drop(&mut self) {
+        {
+            trace!("dropping kmod_list: {:?}", self.list);
+        }
+        unsafe { kmod_sys::kmod_module_info_free_list(self.list) };
+    }
+}
+
+impl InfoIterator {
+    #[inline]
+    pub(crate) fn new(list: *mut kmod_list) -> InfoIterator {
+        trace!("creating kmod_list: {:?}", list);
+        InfoIterator { list, iter: list }
+    }
+}
+
+impl Iterator for InfoIterator {
+    type Item = (String, OsString);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        trace!("kmod_list->next: {:?}", self.iter);
+
+        if self.iter.is_null() {
+            return None;
+        }
+
+        let key = unsafe { kmod_sys::kmod_module_info_get_key(self.iter) };
+        let value = unsafe { kmod_sys::kmod_module_info_get_value(self.iter) };
+        let new_iter = unsafe { kmod_sys::kmod_list_next(self.list, self.iter) };
+        self.iter = new_iter;
+
+        if key.is_null() {
+            panic!("Empty modinfo key");
+        }
+
+        let key = unsafe { CStr::from_ptr(key) }
+            .to_string_lossy()
+            .into_owned();
+        // a key without `=` has no value
+        let value = unsafe { value.as_ref().map(|ptr| CStr::from_ptr(ptr)) }
+            .map(|v| OsStr::from_bytes(v.to_bytes()).to_os_string())
+            .unwrap_or_default();
+
+        Some((key, value))
+    }
+}
+
+impl fmt::Debug for InfoIterator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("InfoIterator { .. }")
+    }
+}
+
+/// Iterator over a kmod_list of symbol versions, the symbol name and its CRC
+pub struct VersionIterator {
+    list: *mut kmod_list,
+    iter: *mut kmod_list,
+}
+
+impl Drop for VersionIterator {
+    fn drop(&mut self) {
+        {
+            trace!("dropping kmod_list: {:?}", self.list);
+        }
+        unsafe { kmod_sys::kmod_module_versions_free_list(self.list) };
+    }
+}
+
+impl VersionIterator {
+    #[inline]
+    pub(crate) fn new(list: *mut kmod_list) -> VersionIterator {
+        trace!("creating kmod_list: {:?}", list);
+        VersionIterator { list, iter: list }
+    }
+}
+
+impl Iterator for VersionIterator {
+    type Item = (String, u64);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        trace!("kmod_list->next: {:?}", self.iter);
+
+        if self.iter.is_null() {
+            return None;
+        }
+
+        let symbol = unsafe { kmod_sys::kmod_module_version_get_symbol(self.iter) };
+        let crc = unsafe { kmod_sys::kmod_module_version_get_crc(self.iter) };
+        let new_iter = unsafe { kmod_sys::kmod_list_next(self.list, self.iter) };
+        self.iter = new_iter;
+
+        if symbol.is_null() {
+            panic!("Empty symbol");
+        }
+
+        let symbol = unsafe { CStr::from_ptr(symbol) };
+
+        Some((symbol.to_string_lossy().into_owned(), crc))
+    }
+}
+
+impl fmt::Debug for VersionIterator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("VersionIterator { .. }")
+    }
+}
@@ -0,0 +1,263 @@
+//! Dynamic string token expansion for `DT_RPATH` and `DT_RUNPATH`
+//!
+//! Follows the rules of glibc's `ld.so`:
+//! * `$ORIGIN`, `$LIB` and `$PLATFORM` are recognized with and without braces
+//! * an unbraced token must not be followed by an alphanumeric character or `_`
+//! * unknown tokens are kept literally
+//! * in a setuid or setgid process `$ORIGIN` is only accepted as the first path
+//!   component and, for the executable itself, has to expand to a trusted directory
+
+use std::ffi::{OsStr, OsString};
+use std::os::unix::prelude::*;
+use std::path::Path;
+
+use super::types::{Class, Endianness, Machine};
+use super::Header;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DstError {
+    /// `ld.so` refuses to expand `$ORIGIN` in this position of a secure process
+    InsecureOrigin(OsString),
+    /// the expansion of `$ORIGIN` leaves the trusted directories of a secure executable
+    UntrustedOrigin(OsString),
+}
+
+impl std::fmt::Display for DstError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DstError::InsecureOrigin(p) => write!(
+                f,
+                "ld.so refuses to expand $ORIGIN in {:?} for a setuid/setgid binary",
+                p
+            ),
+            DstError::UntrustedOrigin(p) => write!(
+                f,
+                "$ORIGIN in {:?} expands to an untrusted directory for a setuid/setgid binary",
+                p
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DstError {}
+
+/// The values of the dynamic string tokens for one object
+#[derive(Debug, Clone)]
+pub struct DynamicStringTokens {
+    /// directory containing the object
+    pub origin: OsString,
+    /// value of `$LIB`, `None` if unknown for the machine
+    pub lib: Option<&'static str>,
+    /// value of `$PLATFORM`, `None` if unknown for the machine
+    pub platform: Option<&'static str>,
+    /// the object is loaded in a setuid or setgid process
+    pub secure: bool,
+}
+
+impl DynamicStringTokens {
+    pub fn new(path: &Path, header: &Header, secure: bool) -> DynamicStringTokens {
+        DynamicStringTokens {
+            origin: path
+                .parent()
+                .map(|p| p.as_os_str().to_os_string())
+                .unwrap_or_default(),
+            lib: lib_name(header),
+            platform: platform_name(header),
+            secure,
+        }
+    }
+
+    /// Expand all tokens in a single search path element.
+    ///
+    /// Returns `Ok(None)`, if the element has to be ignored, because a token has no value.
+    /// `trusted` are the trusted directories, which `$ORIGIN` of a secure executable
+    /// has to expand to.
+    pub fn expand(
+        &self,
+        element: &[u8],
+        trusted: Option<&[OsString]>,
+    ) -> Result<Option<OsString>, DstError> {
+        let mut res = Vec::with_capacity(element.len());
+        let mut origin_used = false;
+        let mut i = 0;
+
+        while i < element.len() {
+            if element[i] == b'$' {
+                let rest = &element[i + 1..];
+
+                let len = is_dst(rest, b"ORIGIN");
+                if len != 0 {
+                    if self.secure && (i != 0 || !(rest.len() == len || rest[len] == b'/')) {
+                        return Err(DstError::InsecureOrigin(OsString::from_vec(
+                            element.to_vec(),
+                        )));
+                    }
+                    res.extend_from_slice(self.origin.as_bytes());
+                    origin_used = true;
+                    i += 1 + len;
+                    continue;
+                }
+
+                let len = is_dst(rest, b"PLATFORM");
+                if len != 0 {
+                    match self.platform {
+                        Some(p) => res.extend_from_slice(p.as_bytes()),
+                        None => return Ok(None),
+                    }
+                    i += 1 + len;
+                    continue;
+                }
+
+                let len = is_dst(rest, b"LIB");
+                if len != 0 {
+                    match self.lib {
+                        Some(l) => res.extend_from_slice(l.as_bytes()),
+                        None => return Ok(None),
+                    }
+                    i += 1 + len;
+                    continue;
+                }
+            }
+            res.push(element[i]);
+            i += 1;
+        }
+
+        if let (true, true, Some(trusted)) = (self.secure, origin_used, trusted) {
+            let dir = Path::new(OsStr::from_bytes(&res));
+            if !trusted.iter().any(|t| dir == Path::new(t)) {
+                return Err(DstError::UntrustedOrigin(OsString::from_vec(
+                    element.to_vec(),
+                )));
+            }
+        }
+
+        Ok(Some(OsString::from_vec(res)))
+    }
+}
+
+/// Check, if `s` (following a `$`) starts with the token `name`.
+///
+/// Returns the length of the token including braces or 0.
+fn is_dst(s: &[u8], name: &[u8]) -> usize {
+    if s.first() == Some(&b'{') {
+        if s[1..].starts_with(name) && s.get(name.len() + 1) == Some(&b'}') {
+            return name.len() + 2;
+        }
+    } else if s.starts_with(name)
+        && !matches!(s.get(name.len()), Some(c) if c.is_ascii_alphanumeric() || *c == b'_')
+    {
+        return name.len();
+    }
+    0
+}
+
+/// The value of `$LIB` for the class and machine of an object
+pub fn lib_name(header: &Header) -> Option<&'static str> {
+    match (&header.ident_class, &header.machine) {
+        (Class::Class32, Machine::X86_64) => Some("libx32"),
+        (Class::Class32, _) => Some("lib"),
+        (Class::Class64, Machine::RISCV) => Some("lib64/lp64d"),
+        (Class::Class64, Machine::X86_64)
+        | (Class::Class64, Machine::AARCH64)
+        | (Class::Class64, Machine::PPC64)
+        | (Class::Class64, Machine::S390)
+        | (Class::Class64, Machine::SPARCV9)
+        | (Class::Class64, Machine::MIPS)
+        | (Class::Class64, Machine::IA_64) => Some("lib64"),
+        (Class::Class64, _) => None,
+    }
+}
+
+/// The generic value of `$PLATFORM` for the class and machine of an object
+pub fn platform_name(header: &Header) -> Option<&'static str> {
+    match (
+        &header.ident_class,
+        &header.machine,
+        &header.ident_endianness,
+    ) {
+        (Class::Class64, Machine::X86_64, _) => Some("x86_64"),
+        (Class::Class32, Machine::X86_64, _) => Some("x86_64"),
+        (Class::Class32, Machine::EM386, _) => Some("i686"),
+        (Class::Class64, Machine::AARCH64, _) => Some("aarch64"),
+        (Class::Class32, Machine::ARM, _) => Some("v7l"),
+        (Class::Class64, Machine::PPC64, Endianness::LittleEndian) => Some("ppc64le"),
+        (Class::Class64, Machine::PPC64, Endianness::BigEndian) => Some("ppc64"),
+        (Class::Class32, Machine::PPC, _) => Some("ppc"),
+        (Class::Class64, Machine::S390, _) => Some("s390x"),
+        (Class::Class32, Machine::S390, _) => Some("s390"),
+        (Class::Class64, Machine::RISCV, _) => Some("riscv64"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DstError, DynamicStringTokens};
+    use std::ffi::OsString;
+
+    fn tokens(secure: bool) -> DynamicStringTokens {
+        DynamicStringTokens {
+            origin: OsString::from("/usr/lib64"),
+            lib: Some("lib64"),
+            platform: Some("x86_64"),
+            secure,
+        }
+    }
+
+    #[test]
+    fn test_expand() {
+        let t = tokens(false);
+        let expand = |e: &str| t.expand(e.as_bytes(), None).unwrap();
+
+        assert_eq!(expand("test"), Some(OsString::from("test")));
+        assert_eq!(expand("$ORIGIN"), Some(OsString::from("/usr/lib64")));
+        assert_eq!(expand("${ORIGIN}/x"), Some(OsString::from("/usr/lib64/x")));
+        assert_eq!(expand("/$ORIGIN/"), Some(OsString::from("//usr/lib64/")));
+        assert_eq!(expand("/_ORIGIN/"), Some(OsString::from("/_ORIGIN/")));
+        assert_eq!(expand("$ORIGINX/"), Some(OsString::from("$ORIGINX/")));
+        assert_eq!(expand("/opt/$LIB"), Some(OsString::from("/opt/lib64")));
+        assert_eq!(
+            expand("/opt/${LIB}_x/$PLATFORM"),
+            Some(OsString::from("/opt/lib64_x/x86_64"))
+        );
+        assert_eq!(expand("/opt/$FOO"), Some(OsString::from("/opt/$FOO")));
+
+        let t = DynamicStringTokens {
+            platform: None,
+            ..tokens(false)
+        };
+        assert_eq!(t.expand(b"/opt/$PLATFORM", None), Ok(None));
+    }
+
+    #[test]
+    fn test_expand_secure() {
+        let t = tokens(true);
+        let trusted = [OsString::from("/usr/lib64/x")];
+
+        assert_eq!(
+            t.expand(b"$ORIGIN/x", None),
+            Ok(Some(OsString::from("/usr/lib64/x")))
+        );
+        assert_eq!(
+            t.expand(b"/opt/$LIB", None),
+            Ok(Some(OsString::from("/opt/lib64")))
+        );
+        assert!(matches!(
+            t.expand(b"/opt/$ORIGIN", None),
+            Err(DstError::InsecureOrigin(_))
+        ));
+        assert!(matches!(t.expand(b"$ORIGIN_x", None), Ok(Some(_))));
+        assert!(matches!(
+            t.expand(b"${ORIGIN}x", None),
+            Err(DstError::InsecureOrigin(_))
+        ));
+        assert_eq!(
+            t.expand(b"$ORIGIN/x", Some(&trusted)),
+            Ok(Some(OsString::from("/usr/lib64/x")))
+        );
+        assert!(matches!(
+            t.expand(b"$ORIGIN/y", Some(&trusted)),
+            Err(DstError::UntrustedOrigin(_))
+        ));
+    }
+}
@@ -2,8 +2,10 @@
 
 use super::error::Error;
 use super::header::Header;
+use super::note::Note;
 use super::section::*;
 use super::segment::*;
+use super::types;
 
 use std::io::{Read, Seek, SeekFrom};
 
@@ -100,4 +102,64 @@ impl Elf {
 
         Ok(())
     }
+
+    /// Load all notes of the object.
+    ///
+    /// The notes are read from the `SHT_NOTE` sections or, if the section headers
+    /// were stripped, from the `PT_NOTE` segments.
+    pub fn notes<R>(&mut self, io: &mut R) -> Result<Vec<Note>, Error>
+    where
+        R: Read + Seek,
+    {
+        let mut notes = Vec::new();
+        let mut have_sections = false;
+
+        for i in 0..self.sections.len() {
+            if self.sections[i].header.shtype == types::SectionType::NOTE {
+                have_sections = true;
+                self.load(i, io)?;
+                if let Some(n) = self.sections[i].content.as_notes() {
+                    notes.extend(n.iter().cloned());
+                }
+            }
+        }
+
+        if have_sections {
+            return Ok(notes);
+        }
+
+        for segment in self.segments.iter() {
+            if segment.phtype == types::SegmentType::NOTE {
+                io.seek(SeekFrom::Start(segment.offset))?;
+                let mut bb = vec![0; segment.filesz as usize];
+                io.read_exact(&mut bb)?;
+                notes.extend(Note::parse(&bb, &self.header, segment.align)?);
+            }
+        }
+
+        Ok(notes)
+    }
+
+    /// The program interpreter requested by the `PT_INTERP` segment
+    pub fn interpreter<R>(&self, io: &mut R) -> Result<Option<Vec<u8>>, Error>
+    where
+        R: Read + Seek,
+    {
+        let segment = match self
+            .segments
+            .iter()
+            .find(|s| s.phtype == types::SegmentType::INTERP)
+        {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+
+        io.seek(SeekFrom::Start(segment.offset))?;
+        let mut bb = vec![0; segment.filesz as usize];
+        io.read_exact(&mut bb)?;
+        if let Some(end) = bb.iter().position(|b| *b == 0) {
+            bb.truncate(end);
+        }
+        Ok(Some(bb))
+    }
 }
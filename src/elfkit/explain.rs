@@ -0,0 +1,162 @@
+//! Traces of the library resolution of [`Ldd`](crate::elfkit::ldd::Ldd)
+//!
+//! A [`Trace`] records every candidate tried for one dependency of an object,
+//! in the order `ld.so` tries them, why it was rejected and which one was chosen.
+
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::path::Path;
+
+use super::Header;
+
+/// Where a candidate came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceSource {
+    /// the dependency contains a slash and is loaded as is
+    Direct,
+    /// `DT_RPATH` of the requester or one of its loaders
+    Rpath,
+    /// `DT_RUNPATH` of the requester
+    Runpath,
+    LdSoCache,
+    /// `ld.so.conf` in place of a missing `ld.so.cache`
+    LdSoConf,
+    /// `/etc/ld-musl-$ARCH.path` or the musl default directories
+    MuslPath,
+    DefaultLibdir,
+}
+
+impl fmt::Display for TraceSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            TraceSource::Direct => "path",
+            TraceSource::Rpath => "RPATH",
+            TraceSource::Runpath => "RUNPATH",
+            TraceSource::LdSoCache => "ld.so.cache",
+            TraceSource::LdSoConf => "ld.so.conf",
+            TraceSource::MuslPath => "musl path",
+            TraceSource::DefaultLibdir => "default",
+        })
+    }
+}
+
+/// Why a candidate was rejected
+#[derive(Debug, Clone)]
+pub enum Rejection {
+    NotFound,
+    /// the ELF header could not be read
+    Unreadable(String),
+    /// built for another class, machine, byte order or OS ABI
+    Incompatible(Header),
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rejection::NotFound => f.write_str("not found"),
+            Rejection::Unreadable(e) => write!(f, "unreadable: {}", e),
+            Rejection::Incompatible(h) => write!(
+                f,
+                "incompatible: {:?} {:?} {:?} {:?}",
+                h.ident_class, h.machine, h.ident_endianness, h.ident_abi
+            ),
+        }
+    }
+}
+
+/// A candidate tried for a dependency
+#[derive(Debug, Clone)]
+pub struct TraceStep {
+    pub source: TraceSource,
+    pub candidate: OsString,
+    /// `None`, if the candidate satisfies the dependency
+    pub rejected: Option<Rejection>,
+}
+
+/// The resolution of one dependency of an object
+#[derive(Debug, Clone)]
+pub struct Trace {
+    pub requester: OsString,
+    pub dependency: OsString,
+    /// the dependency is a soname of a `.note.dlopen` feature
+    pub dlopen: bool,
+    pub steps: Vec<TraceStep>,
+    /// the first candidate satisfying the dependency
+    pub chosen: Option<OsString>,
+}
+
+impl Trace {
+    /// Check, if the trace explains `target`, a soname or the path of a library.
+    ///
+    /// A path matches the chosen candidate and any dependency with the same file name.
+    pub fn matches(&self, target: &OsStr) -> bool {
+        if self.dependency == target || self.chosen.as_deref() == Some(target) {
+            return true;
+        }
+        matches!(Path::new(target).file_name(), Some(name) if name == self.dependency)
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:?} {} {:?}:",
+            self.requester,
+            if self.dlopen { "dlopens" } else { "needs" },
+            self.dependency
+        )?;
+        for step in self.steps.iter() {
+            let verdict = match step.rejected {
+                Some(ref r) => r.to_string(),
+                None if self.chosen.as_ref() == Some(&step.candidate) => "chosen".into(),
+                None => "also installed".into(),
+            };
+            writeln!(f, "  {:<12} {:?}: {}", step.source, step.candidate, verdict)?;
+        }
+        if self.chosen.is_none() {
+            writeln!(f, "  not found")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Rejection, Trace, TraceSource, TraceStep};
+    use std::ffi::{OsStr, OsString};
+
+    #[test]
+    fn test_trace() {
+        let trace = Trace {
+            requester: "/usr/bin/prog".into(),
+            dependency: "libfoo.so.1".into(),
+            dlopen: false,
+            steps: vec![
+                TraceStep {
+                    source: TraceSource::Runpath,
+                    candidate: "/opt/lib/libfoo.so.1".into(),
+                    rejected: Some(Rejection::NotFound),
+                },
+                TraceStep {
+                    source: TraceSource::LdSoCache,
+                    candidate: "/usr/lib64/libfoo.so.1".into(),
+                    rejected: None,
+                },
+            ],
+            chosen: Some(OsString::from("/usr/lib64/libfoo.so.1")),
+        };
+
+        assert!(trace.matches(OsStr::new("libfoo.so.1")));
+        assert!(trace.matches(OsStr::new("/usr/lib64/libfoo.so.1")));
+        assert!(trace.matches(OsStr::new("/opt/other/libfoo.so.1")));
+        assert!(!trace.matches(OsStr::new("libfoo.so.2")));
+
+        assert_eq!(
+            trace.to_string(),
+            "\"/usr/bin/prog\" needs \"libfoo.so.1\":\n  \
+             RUNPATH      \"/opt/lib/libfoo.so.1\": not found\n  \
+             ld.so.cache  \"/usr/lib64/libfoo.so.1\": chosen\n"
+        );
+    }
+}
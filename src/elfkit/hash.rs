@@ -0,0 +1,260 @@
+//! Symbol hash tables as used by the dynamic linker to look up exported symbols
+//!
+//! `SHT_HASH` is the classic System V table, `SHT_GNU_HASH` the table
+//! produced by `ld --hash-style=gnu`. Both index into the `.dynsym`
+//! section they are linked to.
+
+use super::symbol::Symbol;
+use super::types;
+use super::{Error, Header, SectionContent};
+use std::io::Read;
+
+/// The System V hash table (`SHT_HASH`)
+#[derive(Debug, Clone, Default)]
+pub struct SysvHash {
+    pub buckets: Vec<u32>,
+    pub chains: Vec<u32>,
+}
+
+impl SysvHash {
+    pub fn from_reader<R>(
+        mut io: R,
+        _linked: Option<&SectionContent>,
+        eh: &Header,
+    ) -> Result<SectionContent, Error>
+    where
+        R: Read,
+    {
+        // s390x and alpha use 64 bit hash table entries
+        let wide = eh.ident_class == types::Class::Class64
+            && matches!(eh.machine, types::Machine::S390 | types::Machine::ALPHA);
+        let entsize = if wide { 8 } else { 4 };
+
+        let mut data = Vec::new();
+        io.read_to_end(&mut data)?;
+        let mut io = data.as_slice();
+
+        let words = elf_dispatch_endianness!(eh => {
+            let mut read_word = || -> std::io::Result<u32> {
+                if wide {
+                    Ok(read_u64(&mut io)? as u32)
+                } else {
+                    read_u32(&mut io)
+                }
+            };
+            let nbucket = read_word()?;
+            let nchain = read_word()?;
+            // the counts are taken from the file, the entries have to fit in the section
+            if (u64::from(nbucket) + u64::from(nchain)) * entsize > data.len() as u64 - 2 * entsize {
+                return Err(Error::UnexpectedSectionContent);
+            }
+            let (nbucket, nchain) = (nbucket as usize, nchain as usize);
+            let mut words = Vec::with_capacity(nbucket + nchain);
+            for _ in 0..(nbucket + nchain) {
+                words.push(read_word()?);
+            }
+            (nbucket, words)
+        });
+        let (nbucket, mut buckets) = words;
+        let chains = buckets.split_off(nbucket);
+
+        Ok(SectionContent::Hash(SysvHash { buckets, chains }))
+    }
+
+    /// Look up the indexes of the symbols named `name` in `symbols`, the linked
+    /// `.dynsym` section
+    pub fn lookup(&self, name: &[u8], symbols: &[Symbol]) -> Vec<usize> {
+        let mut found = Vec::new();
+        if self.buckets.is_empty() {
+            return found;
+        }
+        let mut idx = self.buckets[sysv_hash(name) as usize % self.buckets.len()] as usize;
+        // a malformed chain may loop
+        for _ in 0..self.chains.len() {
+            if idx == 0 {
+                break;
+            }
+            match symbols.get(idx) {
+                Some(sym) if sym.name == name => found.push(idx),
+                Some(_) => {}
+                None => break,
+            }
+            idx = match self.chains.get(idx) {
+                Some(next) => *next as usize,
+                None => break,
+            };
+        }
+        found
+    }
+}
+
+/// The GNU hash table (`SHT_GNU_HASH`)
+#[derive(Debug, Clone, Default)]
+pub struct GnuHash {
+    /// index of the first symbol in `.dynsym` covered by the table
+    pub symoffset: u32,
+    pub bloom_shift: u32,
+    /// the bloom filter words, `ELFCLASS` sized
+    pub bloom: Vec<u64>,
+    pub buckets: Vec<u32>,
+    pub chain: Vec<u32>,
+    /// bits per bloom filter word
+    word_bits: u32,
+}
+
+impl GnuHash {
+    pub fn from_reader<R>(
+        mut io: R,
+        _linked: Option<&SectionContent>,
+        eh: &Header,
+    ) -> Result<SectionContent, Error>
+    where
+        R: Read,
+    {
+        let word_bits = match eh.ident_class {
+            types::Class::Class32 => 32,
+            types::Class::Class64 => 64,
+        };
+
+        let mut data = Vec::new();
+        io.read_to_end(&mut data)?;
+        let mut io = data.as_slice();
+
+        let r = elf_dispatch_endianness!(eh => {
+            elf_dispatch_uclass!(eh => {
+                let nbuckets = read_u32(&mut io)?;
+                let symoffset = read_u32(&mut io)?;
+                let bloom_size = read_u32(&mut io)?;
+                let bloom_shift = read_u32(&mut io)?;
+                // the counts are taken from the file, the entries have to fit in the section
+                if u64::from(bloom_size) * u64::from(word_bits / 8) + u64::from(nbuckets) * 4
+                    > io.len() as u64
+                {
+                    return Err(Error::UnexpectedSectionContent);
+                }
+
+                let mut bloom = Vec::with_capacity(bloom_size as usize);
+                for _ in 0..bloom_size {
+                    bloom.push(read_uclass(&mut io)?);
+                }
+                let mut buckets = Vec::with_capacity(nbuckets as usize);
+                for _ in 0..nbuckets {
+                    buckets.push(read_u32(&mut io)?);
+                }
+                let mut chain = Vec::new();
+                while let Ok(v) = read_u32(&mut io) {
+                    chain.push(v);
+                }
+                GnuHash {
+                    symoffset,
+                    bloom_shift,
+                    bloom,
+                    buckets,
+                    chain,
+                    word_bits,
+                }
+            })
+        });
+
+        Ok(SectionContent::GnuHash(r))
+    }
+
+    /// Look up the indexes of the symbols named `name` in `symbols`, the linked
+    /// `.dynsym` section
+    pub fn lookup(&self, name: &[u8], symbols: &[Symbol]) -> Vec<usize> {
+        let mut found = Vec::new();
+        if self.buckets.is_empty() || self.bloom.is_empty() {
+            return found;
+        }
+        let h = gnu_hash(name);
+
+        let word = self.bloom[(h / self.word_bits) as usize % self.bloom.len()];
+        let mask =
+            (1u64 << (h % self.word_bits)) | (1u64 << ((h >> self.bloom_shift) % self.word_bits));
+        if word & mask != mask {
+            return found;
+        }
+
+        let mut idx = self.buckets[h as usize % self.buckets.len()];
+        if idx < self.symoffset {
+            return found;
+        }
+        // symbols of the same name, like several versions, follow each other in the chain
+        while let Some(h2) = self.chain.get((idx - self.symoffset) as usize) {
+            if (h | 1) == (h2 | 1) {
+                match symbols.get(idx as usize) {
+                    Some(sym) if sym.name == name => found.push(idx as usize),
+                    Some(_) => {}
+                    None => break,
+                }
+            }
+            if h2 & 1 != 0 {
+                break;
+            }
+            idx += 1;
+        }
+        found
+    }
+}
+
+/// The hash function of `SHT_HASH`
+pub fn sysv_hash(name: &[u8]) -> u32 {
+    let mut h = 0u32;
+    for c in name {
+        h = (h << 4).wrapping_add(u32::from(*c));
+        let g = h & 0xf000_0000;
+        if g != 0 {
+            h ^= g >> 24;
+        }
+        h &= !g;
+    }
+    h
+}
+
+/// The hash function of `SHT_GNU_HASH`
+pub fn gnu_hash(name: &[u8]) -> u32 {
+    name.iter().fold(5381u32, |h, c| {
+        h.wrapping_mul(33).wrapping_add(u32::from(*c))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{gnu_hash, sysv_hash, GnuHash, SysvHash};
+    use crate::elfkit::{types, Header};
+
+    #[test]
+    fn test_hash_functions() {
+        assert_eq!(sysv_hash(b""), 0);
+        assert_eq!(sysv_hash(b"printf"), 0x077905a6);
+        assert_eq!(sysv_hash(b"exit"), 0x0006cf04);
+        assert_eq!(gnu_hash(b""), 0x00001505);
+        assert_eq!(gnu_hash(b"printf"), 0x156b2bb8);
+        assert_eq!(gnu_hash(b"exit"), 0x7c967e3f);
+    }
+
+    #[test]
+    fn test_counts_exceed_section() {
+        let eh = Header {
+            ident_class: types::Class::Class64,
+            ident_endianness: types::Endianness::LittleEndian,
+            ..Default::default()
+        };
+        let mut table = Vec::new();
+        for word in [1u32, 2, 0, 0, 1] {
+            table.extend_from_slice(&word.to_le_bytes());
+        }
+        assert!(SysvHash::from_reader(table.as_slice(), None, &eh).is_ok());
+
+        // nbucket and nchain, which do not fit in the section
+        table[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(SysvHash::from_reader(table.as_slice(), None, &eh).is_err());
+
+        // nbuckets, symoffset, bloom_size and bloom_shift
+        let mut table = Vec::new();
+        for word in [1u32, 1, u32::MAX, 6] {
+            table.extend_from_slice(&word.to_le_bytes());
+        }
+        assert!(GnuHash::from_reader(table.as_slice(), None, &eh).is_err());
+    }
+}
@@ -2,7 +2,7 @@ use super::error::Error;
 use num_traits::FromPrimitive;
 
 use super::types;
-use std::io::Read;
+use std::io::{Read, Write};
 
 #[derive(Debug, Clone)]
 pub struct Header {
@@ -135,4 +135,33 @@ impl Header {
 
         Ok(r)
     }
+
+    pub fn to_writer<W>(&self, io: &mut W) -> Result<(), Error>
+    where
+        W: Write,
+    {
+        let mut ident = [0; 16];
+        ident[0..4].clone_from_slice(&self.ident_magic);
+        ident[4] = self.ident_class.clone() as u8;
+        ident[5] = self.ident_endianness.clone() as u8;
+        ident[6] = self.ident_version;
+        ident[7] = self.ident_abi.clone() as u8;
+        ident[8] = self.ident_abiversion;
+        io.write_all(&ident)?;
+
+        elf_write_u16!(self, io, self.etype.clone() as u16)?;
+        elf_write_u16!(self, io, self.machine.clone() as u16)?;
+        elf_write_u32!(self, io, self.version)?;
+        elf_write_uclass!(self, io, self.entry)?;
+        elf_write_uclass!(self, io, self.phoff)?;
+        elf_write_uclass!(self, io, self.shoff)?;
+        elf_write_u32!(self, io, self.flags.bits())?;
+        elf_write_u16!(self, io, self.ehsize)?;
+        elf_write_u16!(self, io, self.phentsize)?;
+        elf_write_u16!(self, io, self.phnum)?;
+        elf_write_u16!(self, io, self.shentsize)?;
+        elf_write_u16!(self, io, self.shnum)?;
+        elf_write_u16!(self, io, self.shstrndx)?;
+        Ok(())
+    }
 }
@@ -0,0 +1,157 @@
+//! The library directories configured in `/etc/ld.so.conf`
+//!
+//! `ld.so` itself never reads this file, `ldconfig` builds the `ld.so.cache` from it.
+//! For a root directory without a cache, the configured directories are the best
+//! approximation of what the cache would contain.
+
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::os::unix::prelude::*;
+use std::path::{Path, PathBuf};
+
+use hashbrown::HashSet;
+
+use crate::file::in_root;
+
+/// Read `/etc/ld.so.conf` of `sysroot` and all files it includes.
+///
+/// Returns the configured directories in order, as absolute paths inside `sysroot`.
+/// Missing or unreadable files are skipped like `ldconfig` does.
+pub fn read_ld_so_conf(sysroot: &Path) -> Vec<OsString> {
+    let mut dirs = Vec::new();
+    let mut seen = HashSet::new();
+    parse_file(sysroot, Path::new("/etc/ld.so.conf"), &mut dirs, &mut seen);
+    dirs
+}
+
+fn parse_file(sysroot: &Path, path: &Path, dirs: &mut Vec<OsString>, seen: &mut HashSet<PathBuf>) {
+    if !seen.insert(path.to_path_buf()) {
+        return;
+    }
+
+    let content = match fs::read(in_root(sysroot, path)) {
+        Ok(c) => c,
+        Err(_) => return,
+    };
+
+    for line in content.split(|b| *b == b'\n') {
+        let line = line.split(|b| *b == b'#').next().unwrap_or_default();
+        let mut words = line
+            .split(|b| b.is_ascii_whitespace() || *b == b',' || *b == b':')
+            .filter(|w| !w.is_empty());
+
+        match words.next() {
+            None => {}
+            Some(b"include") => {
+                let base = path.parent().unwrap_or_else(|| Path::new("/"));
+                for pattern in words {
+                    for inc in glob(sysroot, &base.join(OsStr::from_bytes(pattern))) {
+                        parse_file(sysroot, &inc, dirs, seen);
+                    }
+                }
+            }
+            // `hwcap` lines do not add directories
+            Some(b"hwcap") => {}
+            Some(first) => {
+                for dir in std::iter::once(first).chain(words) {
+                    // a trailing `=TYPE` sets the library type for old libc5 setups
+                    let dir = dir.split(|b| *b == b'=').next().unwrap_or_default();
+                    let dir = OsString::from_vec(dir.to_vec());
+                    if !dir.is_empty() && !dirs.contains(&dir) {
+                        dirs.push(dir);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Expand the wildcards `*` and `?` in the file name of `pattern`.
+///
+/// Matches are sorted like `glob(3)` returns them.
+fn glob(sysroot: &Path, pattern: &Path) -> Vec<PathBuf> {
+    let name = match pattern.file_name() {
+        Some(n) => n.as_bytes(),
+        None => return Vec::new(),
+    };
+    if !name.iter().any(|b| *b == b'*' || *b == b'?') {
+        return vec![pattern.to_path_buf()];
+    }
+
+    let dir = pattern.parent().unwrap_or_else(|| Path::new("/"));
+    let mut res = match fs::read_dir(in_root(sysroot, dir)) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .map(|e| e.file_name())
+            .filter(|n| !n.as_bytes().starts_with(b".") && wildcard_match(name, n.as_bytes()))
+            .map(|n| dir.join(n))
+            .collect::<Vec<_>>(),
+        Err(_) => Vec::new(),
+    };
+    res.sort();
+    res
+}
+
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            wildcard_match(&pattern[1..], name)
+                || (!name.is_empty() && wildcard_match(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => wildcard_match(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => wildcard_match(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_ld_so_conf, wildcard_match};
+    use std::ffi::OsString;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match(b"*.conf", b"libc.conf"));
+        assert!(wildcard_match(b"*.conf", b".conf"));
+        assert!(!wildcard_match(b"*.conf", b"libc.conf.rpmsave"));
+        assert!(wildcard_match(b"lib?.conf", b"libc.conf"));
+        assert!(!wildcard_match(b"lib?.conf", b"lib.conf"));
+        assert!(wildcard_match(b"*", b""));
+    }
+
+    #[test]
+    fn test_read_ld_so_conf() {
+        let tmp_dir = TempDir::new().unwrap();
+        let etc = tmp_dir.path().join("etc");
+        fs::create_dir_all(etc.join("ld.so.conf.d")).unwrap();
+        fs::write(
+            etc.join("ld.so.conf"),
+            "# comment\ninclude ld.so.conf.d/*.conf\n/usr/local/lib # local\nhwcap 1 nosegneg\n\
+             include /etc/ld.so.conf\n",
+        )
+        .unwrap();
+        fs::write(
+            etc.join("ld.so.conf.d/b.conf"),
+            "/opt/b/lib, /opt/c/lib=libc6\n",
+        )
+        .unwrap();
+        fs::write(
+            etc.join("ld.so.conf.d/a.conf"),
+            "/opt/a/lib\n\n/usr/local/lib\n",
+        )
+        .unwrap();
+        fs::write(etc.join("ld.so.conf.d/x.txt"), "/opt/x/lib\n").unwrap();
+
+        assert_eq!(
+            read_ld_so_conf(tmp_dir.path()),
+            ["/opt/a/lib", "/usr/local/lib", "/opt/b/lib", "/opt/c/lib"]
+                .iter()
+                .map(OsString::from)
+                .collect::<Vec<_>>()
+        );
+        assert!(read_ld_so_conf(&tmp_dir.path().join("nonexistent")).is_empty());
+    }
+}
@@ -8,195 +8,843 @@ use std::sync::RwLock;
 
 use hashbrown::HashMap;
 use hashbrown::HashSet;
+use slog::{debug, o, Logger};
 
-use crate::elfkit::{self, ld_so_cache::LdsoCache, Elf};
+use crate::elfkit::dst::{DstError, DynamicStringTokens};
+use crate::elfkit::explain::{Rejection, Trace, TraceSource, TraceStep};
+use crate::elfkit::musl::{musl_arch, musl_expands, musl_search_path};
+use crate::elfkit::note::{DlopenFeature, DlopenPriority};
+use crate::elfkit::types::Abi;
+use crate::elfkit::{self, ld_so_cache::LdsoCache, Elf, Header, NoteContent, VersionNeed};
+use crate::file::{canonicalize_in, in_root};
 
-fn replace_slice<T: Copy>(buf: &[T], from: &[T], to: &[T]) -> Vec<T>
-where
-    T: Clone + PartialEq,
-{
-    if buf.len() < from.len() {
-        return Vec::from(buf);
+/// Split a `DT_RPATH` or `DT_RUNPATH` value into its directories,
+/// expanding the dynamic string tokens.
+///
+/// Elements, which `ld.so` would refuse to expand, are skipped and collected in `refused`.
+fn split_search_path(
+    value: &[u8],
+    dst: &DynamicStringTokens,
+    trusted: Option<&[OsString]>,
+    refused: &mut Vec<DstError>,
+) -> Vec<OsString> {
+    value
+        .split(|e| *e == b':')
+        .filter_map(|n| match dst.expand(n, trusted) {
+            Ok(v) => v,
+            Err(e) => {
+                refused.push(e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Compute the directories searched for the direct dependencies of an object
+/// and the `DT_RPATH` chain inherited by those dependencies.
+///
+/// The `DT_RPATH` of an object with a `DT_RUNPATH` is ignored,
+/// but the `DT_RPATH` of its loaders is still inherited by its dependencies.
+fn search_paths(
+    rpath: Vec<OsString>,
+    runpath: Option<Vec<OsString>>,
+    inherited_rpath: &[OsString],
+) -> (Vec<OsString>, Vec<OsString>) {
+    match runpath {
+        Some(runpath) => (runpath, inherited_rpath.to_vec()),
+        None => {
+            let mut chain = rpath;
+            for p in inherited_rpath {
+                if !chain.contains(p) {
+                    chain.push(p.clone());
+                }
+            }
+            (chain.clone(), chain)
+        }
     }
+}
 
-    let mut res: Vec<T> = Vec::new();
-    let mut i = 0;
-    while i <= buf.len() - from.len() {
-        if buf[i..].starts_with(from) {
-            res.extend_from_slice(to);
-            i += from.len();
-        } else {
-            res.push(buf[i]);
-            i += 1;
+/// Check, if `ld.so` would load a library with the header `lib`
+/// for an object with the header `requester`.
+fn compatible(requester: &Header, lib: &Header) -> bool {
+    // ELFOSABI_GNU objects are loaded by a ELFOSABI_SYSV loader and vice versa
+    let os_abi = |h: &Header| match h.ident_abi {
+        Abi::GNU => Abi::SYSV,
+        ref abi => abi.clone(),
+    };
+
+    requester.ident_class == lib.ident_class
+        && requester.machine == lib.machine
+        && requester.ident_endianness == lib.ident_endianness
+        && os_abi(requester) == os_abi(lib)
+}
+
+/// An error resolving the dependencies of an object
+#[derive(Debug)]
+pub enum LddError {
+    /// the object could not be opened or read
+    Unreadable { path: OsString, error: io::Error },
+    /// the object is not an ELF file, like a script
+    NotElf { path: OsString },
+    /// the object is an ELF file, which could not be parsed
+    Malformed {
+        path: OsString,
+        error: elfkit::Error,
+    },
+    /// the library `dependency` of `requester` was not found in any of the searched directories
+    MissingDependency {
+        requester: OsString,
+        dependency: OsString,
+        search_path: Vec<OsString>,
+    },
+    /// only libraries built for another class, machine, byte order or OS ABI
+    /// than `requester` were found for `dependency`
+    WrongArchitecture {
+        requester: OsString,
+        dependency: OsString,
+        candidates: Vec<OsString>,
+    },
+    /// `library`, found for `dependency`, does not define the `DT_VERNEED` `version`,
+    /// which `requester` needs
+    MissingVersion {
+        requester: OsString,
+        dependency: OsString,
+        library: OsString,
+        version: OsString,
+    },
+    /// `ld.so` refuses to expand a dynamic string token of `requester`
+    RefusedToken {
+        requester: OsString,
+        error: DstError,
+    },
+    /// none of the sonames of a `.note.dlopen` feature of `requester` was found
+    MissingDlopenFeature {
+        requester: OsString,
+        feature: DlopenFeature,
+        search_path: Vec<OsString>,
+    },
+}
+
+impl std::fmt::Display for LddError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LddError::Unreadable { path, error } => write!(f, "{:?}: {}", path, error),
+            LddError::NotElf { path } => write!(f, "{:?}: not a dynamic executable", path),
+            LddError::Malformed { path, error } => {
+                write!(f, "{:?}: malformed ELF file: {:?}", path, error)
+            }
+            LddError::MissingDependency {
+                requester,
+                dependency,
+                search_path,
+            } => write!(
+                f,
+                "{:?}: unable to find dependency {:?} in {:?}",
+                requester, dependency, search_path
+            ),
+            LddError::WrongArchitecture {
+                requester,
+                dependency,
+                candidates,
+            } => write!(
+                f,
+                "{:?}: dependency {:?} only found for another architecture: {:?}",
+                requester, dependency, candidates
+            ),
+            LddError::MissingVersion {
+                requester,
+                dependency,
+                library,
+                version,
+            } => write!(
+                f,
+                "{:?} needs {} ({}) which {:?} does not provide",
+                requester,
+                dependency.to_string_lossy(),
+                version.to_string_lossy(),
+                library
+            ),
+            LddError::RefusedToken { requester, error } => write!(f, "{:?}: {}", requester, error),
+            LddError::MissingDlopenFeature {
+                requester,
+                feature,
+                search_path,
+            } => write!(
+                f,
+                "{:?}: unable to satisfy {} dlopen feature {:?}, none of {:?} found in {:?}",
+                requester,
+                feature.priority,
+                feature.feature.as_deref().unwrap_or_default(),
+                feature.soname,
+                search_path
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LddError {}
+
+/// The directories searched for a library and the incompatible candidates skipped
+#[derive(Debug)]
+struct Search {
+    searched: Vec<OsString>,
+    incompatible: Vec<OsString>,
+    /// where the current candidates come from
+    source: TraceSource,
+    /// every candidate tried, if traced
+    steps: Option<Vec<TraceStep>>,
+}
+
+impl Search {
+    fn new(trace: bool) -> Search {
+        Search {
+            searched: Vec::new(),
+            incompatible: Vec::new(),
+            source: TraceSource::Direct,
+            steps: if trace { Some(Vec::new()) } else { None },
+        }
+    }
+
+    fn searching(&mut self, dir: &OsStr) {
+        if !self.searched.iter().any(|d| d == dir) {
+            self.searched.push(dir.to_os_string());
         }
     }
 
-    if i < buf.len() {
-        res.extend_from_slice(&buf[i..buf.len()]);
+    fn step(&mut self, candidate: &Path, rejected: Option<Rejection>) {
+        if let Some(ref mut steps) = self.steps {
+            steps.push(TraceStep {
+                source: self.source,
+                candidate: candidate.as_os_str().into(),
+                rejected,
+            });
+        }
+    }
+
+    fn into_error(self, requester: &OsStr, dependency: &OsStr) -> LddError {
+        if self.incompatible.is_empty() {
+            LddError::MissingDependency {
+                requester: requester.into(),
+                dependency: dependency.into(),
+                search_path: self.searched,
+            }
+        } else {
+            LddError::WrongArchitecture {
+                requester: requester.into(),
+                dependency: dependency.into(),
+                candidates: self.incompatible,
+            }
+        }
     }
-    res
 }
 
+/// Resolves the dependencies of ELF objects like `ld.so` does.
+///
+/// All paths are absolute paths inside [`Ldd::sysroot`]. The `ld.so.cache`,
+/// `DT_RPATH`, `DT_RUNPATH`, `$ORIGIN` and the default library directories
+/// are interpreted relative to it and symlinks are resolved within it.
 pub struct Ldd<'a> {
     pub ld_so_cache: Option<&'a LdsoCache>,
+    /// the directories of `ld.so.conf`, searched instead of a missing `ld.so.cache`
+    pub ld_so_conf: Vec<OsString>,
     pub default_libdir: &'a [OsString],
     pub canon_cache: RwLock<HashMap<OsString, OsString>>,
-    pub dest_path: OsString,
+    pub verdef_cache: RwLock<HashMap<OsString, Option<HashSet<Vec<u8>>>>>,
+    /// the system search path of every musl loader seen
+    pub musl_path_cache: RwLock<HashMap<OsString, Vec<OsString>>>,
+    /// the resolved `DT_NEEDED` libraries of every object passed to [`Ldd::recurse`]
+    pub dependencies: RwLock<HashMap<OsString, Vec<OsString>>>,
+    /// the libraries of the `.note.dlopen` features of every object passed to [`Ldd::recurse`]
+    pub dlopen_dependencies: RwLock<HashMap<OsString, Vec<OsString>>>,
+    /// libraries already installed below this directory are not enqueued
+    pub dest_path: Option<OsString>,
+    /// follow the `.note.dlopen` features up to this priority
+    pub dlopen_priority: Option<DlopenPriority>,
+    /// record a [`Trace`] of every dependency resolved in [`Ldd::traces`]
+    pub trace: bool,
+    pub traces: RwLock<Vec<Trace>>,
+    /// the root directory of the objects, `/` by default
+    pub sysroot: PathBuf,
+    pub logger: Logger,
 }
 
-type OsStringDynQueueHandle<'a> = dynqueue::DynQueueHandle<
-    'a,
-    (OsString, HashSet<OsString>),
-    RwLock<Vec<(OsString, HashSet<OsString>)>>,
->;
+/// The state shared by the lookups of the dependencies of one object
+struct Lookup<'b, 'c> {
+    handle: &'b OsStringDynQueueHandle<'c>,
+    visited: &'b RwLock<HashSet<OsString>>,
+    rpath_chain: &'b [OsString],
+    /// the search path of the requester is its `DT_RUNPATH`
+    runpath: bool,
+    secure: bool,
+    /// path and header of the requesting object
+    requester: &'b OsStr,
+    header: &'b Header,
+    /// the program interpreter of the executable
+    interp: Option<&'b OsString>,
+    /// the system search path of the musl loader, `None` for glibc
+    musl_path: Option<&'b [OsString]>,
+}
+
+/// A queued object to be resolved by [`Ldd::recurse`]
+#[derive(Debug, Clone, Default)]
+pub struct LddQueueItem {
+    /// path of the object
+    pub path: OsString,
+    /// `DT_RPATH` directories inherited from the chain of objects which loaded it,
+    /// ordered from the nearest loader up to the executable
+    pub rpath: Vec<OsString>,
+    /// the object is loaded by a setuid or setgid executable
+    pub secure: bool,
+    /// the program interpreter of the executable, which loads the object
+    pub interp: Option<OsString>,
+}
+
+type OsStringDynQueueHandle<'a> =
+    dynqueue::DynQueueHandle<'a, LddQueueItem, RwLock<Vec<LddQueueItem>>>;
 
 impl<'a> Ldd<'a> {
     pub fn new(
         ld_so_cache: Option<&'a LdsoCache>,
         slpath: &'a [OsString],
-        dest_path: &Path,
+        dest_path: Option<&Path>,
     ) -> Ldd<'a> {
         Ldd {
             ld_so_cache,
             default_libdir: slpath,
             canon_cache: RwLock::new(HashMap::new()),
-            dest_path: OsString::from(dest_path.as_os_str()),
+            verdef_cache: RwLock::new(HashMap::new()),
+            musl_path_cache: RwLock::new(HashMap::new()),
+            dependencies: RwLock::new(HashMap::new()),
+            dlopen_dependencies: RwLock::new(HashMap::new()),
+            dest_path: dest_path.map(|p| OsString::from(p.as_os_str())),
+            dlopen_priority: None,
+            trace: false,
+            traces: RwLock::new(Vec::new()),
+            ld_so_conf: Vec::new(),
+            sysroot: PathBuf::from("/"),
+            logger: Logger::root(slog::Discard, o!()),
         }
     }
 
+    /// Resolve the `DT_NEEDED` entries of `path` and enqueue every newly found library.
+    ///
+    /// The search order follows glibc's `ld.so`:
+    /// 1. `DT_RPATH` of `path` and of all its loaders, unless `path` has a `DT_RUNPATH`
+    /// 2. `DT_RUNPATH` of `path` (not inherited by its dependencies)
+    /// 3. the `ld.so.cache` or without a cache the `ld.so.conf` directories,
+    ///    unless `path` is flagged with `DF_1_NODEFLIB`
+    /// 4. the default library directories, unless `path` is flagged with `DF_1_NODEFLIB`
+    ///
+    /// `item.rpath` holds the `DT_RPATH` directories of the loaders of `item.path`.
+    /// A `DT_RPATH` is ignored for objects, which also have a `DT_RUNPATH`.
+    ///
+    /// With [`Ldd::dlopen_priority`] set, the sonames of the `.note.dlopen` features
+    /// of `path` up to this priority are searched the same way. The first soname found
+    /// satisfies a feature.
+    ///
+    /// Objects loaded by the musl loader, as requested by the `PT_INTERP` of the
+    /// executable, are searched like musl does, see [`crate::elfkit::musl`].
+    ///
+    /// Missing dependencies, dynamic string tokens, which `ld.so` refuses to expand
+    /// for setuid or setgid binaries, `DT_VERNEED` versions, which the chosen library
+    /// does not define, and unsatisfied `dlopen()` features are reported as errors
+    /// after all dependencies have been resolved.
     pub fn recurse(
         &self,
         handle: OsStringDynQueueHandle,
-        path: &OsStr,
-        lpaths: &HashSet<OsString>,
+        item: &LddQueueItem,
         visited: &RwLock<HashSet<OsString>>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut lpaths = lpaths.clone();
-        let mut f = File::open(path)?;
+    ) -> Result<(), Vec<LddError>> {
+        let path = item.path.as_os_str();
+        let unreadable = |error| {
+            vec![LddError::Unreadable {
+                path: path.into(),
+                error,
+            }]
+        };
+        let malformed = |error| {
+            vec![LddError::Malformed {
+                path: path.into(),
+                error,
+            }]
+        };
+
+        let mut f = File::open(self.host_path(Path::new(path))).map_err(unreadable)?;
+        let setid = f.metadata().map_err(unreadable)?.mode() & (libc::S_ISUID | libc::S_ISGID) != 0;
         let mut elf = match Elf::from_reader(&mut f) {
             Ok(e) => e,
             Err(elfkit::Error::InvalidMagic) => {
-                return Err("not a dynamic executable".into());
-            }
-            Err(e) => {
-                return Err(format!("{:#?}", e).into());
+                return Err(vec![LddError::NotElf { path: path.into() }]);
             }
+            Err(elfkit::Error::Io(e)) => return Err(unreadable(e)),
+            Err(e) => return Err(malformed(e)),
         };
 
+        let interp = match elf.interpreter(&mut f) {
+            Ok(Some(i)) => Some(OsString::from_vec(i)),
+            _ => item.interp.clone(),
+        };
+        let musl = matches!(interp, Some(ref i) if musl_arch(i.as_bytes()).is_some());
+
+        // musl drops a search path with $ORIGIN of a setuid or setgid executable
+        // as a whole and expands $ORIGIN of its libraries unrestricted
+        let secure = (item.secure || setid) && !musl;
+        let dst = DynamicStringTokens::new(Path::new(path), &elf.header, secure);
+        // only the expansion of $ORIGIN for the setuid executable itself is checked
+        let trusted = if setid && !musl {
+            Some(self.default_libdir)
+        } else {
+            None
+        };
+        let mut refused = Vec::new();
+
+        let mut rpath: Vec<OsString> = Vec::new();
+        let mut runpath: Option<Vec<OsString>> = None;
+        let mut nodeflib = false;
         let mut deps: Vec<OsString> = Vec::new();
+        let mut verneed: Vec<VersionNeed> = Vec::new();
+
         for shndx in 0..elf.sections.len() {
+            if elf.sections[shndx].header.shtype == elfkit::types::SectionType::GNU_VERNEED {
+                elf.load(shndx, &mut f).map_err(malformed)?;
+                if let Some(v) = elf.sections[shndx].content.as_verneed() {
+                    verneed.extend(v.iter().cloned());
+                }
+            }
             if elf.sections[shndx].header.shtype == elfkit::types::SectionType::DYNAMIC {
-                elf.load(shndx, &mut f).unwrap();
-                let dynamic = elf.sections[shndx].content.as_dynamic().unwrap();
+                elf.load(shndx, &mut f).map_err(malformed)?;
+                let dynamic = match elf.sections[shndx].content.as_dynamic() {
+                    Some(d) => d,
+                    None => return Err(malformed(elfkit::Error::UnexpectedSectionContent)),
+                };
 
                 for dyn_entry in dynamic.iter() {
-                    if dyn_entry.dhtype == elfkit::types::DynamicType::RPATH {
-                        if let elfkit::dynamic::DynamicContent::String(ref name) = dyn_entry.content
-                        {
-                            name.0.split(|e| *e == b':').for_each(|n| {
-                                let n = replace_slice(
-                                    &n,
-                                    b"$ORIGIN",
-                                    PathBuf::from(path).parent().unwrap().as_os_str().as_bytes(),
-                                );
-
-                                lpaths.insert(OsString::from(OsStr::from_bytes(&n)));
-                            });
+                    match (&dyn_entry.dhtype, &dyn_entry.content) {
+                        (
+                            elfkit::types::DynamicType::RPATH | elfkit::types::DynamicType::RUNPATH,
+                            elfkit::dynamic::DynamicContent::String(ref name),
+                        ) if musl && !musl_expands(&name.0) => {
+                            debug!(
+                                self.logger,
+                                "{:?}: musl ignores the search path {:?}",
+                                path,
+                                OsStr::from_bytes(&name.0)
+                            );
                         }
-                    }
-                    if dyn_entry.dhtype == elfkit::types::DynamicType::RUNPATH {
-                        if let elfkit::dynamic::DynamicContent::String(ref name) = dyn_entry.content
-                        {
-                            name.0.split(|e| *e == b':').for_each(|n| {
-                                let n = replace_slice(
-                                    &n,
-                                    b"$ORIGIN",
-                                    PathBuf::from(path).parent().unwrap().as_os_str().as_bytes(),
-                                );
-
-                                lpaths.insert(OsString::from(OsStr::from_bytes(&n)));
-                            });
+                        (
+                            elfkit::types::DynamicType::RPATH | elfkit::types::DynamicType::RUNPATH,
+                            elfkit::dynamic::DynamicContent::String(ref name),
+                        ) if musl && setid && name.0.contains(&b'$') => {
+                            refused
+                                .push(DstError::InsecureOrigin(OsStr::from_bytes(&name.0).into()));
                         }
-                    }
-                    if dyn_entry.dhtype == elfkit::types::DynamicType::NEEDED {
-                        if let elfkit::dynamic::DynamicContent::String(ref name) = dyn_entry.content
-                        {
+                        (
+                            elfkit::types::DynamicType::RPATH,
+                            elfkit::dynamic::DynamicContent::String(ref name),
+                        ) => {
+                            rpath.extend(split_search_path(&name.0, &dst, trusted, &mut refused));
+                        }
+                        (
+                            elfkit::types::DynamicType::RUNPATH,
+                            elfkit::dynamic::DynamicContent::String(ref name),
+                        ) => {
+                            runpath
+                                .get_or_insert_with(Vec::new)
+                                .extend(split_search_path(&name.0, &dst, trusted, &mut refused));
+                        }
+                        (
+                            elfkit::types::DynamicType::NEEDED,
+                            elfkit::dynamic::DynamicContent::String(ref name),
+                        ) => {
                             deps.push(OsStr::from_bytes(&name.0).into());
                         }
+                        (
+                            elfkit::types::DynamicType::FLAGS_1,
+                            elfkit::dynamic::DynamicContent::Flags1(flags),
+                        ) => {
+                            nodeflib = flags.contains(elfkit::types::DynamicFlags1::NODEFLIB);
+                        }
+                        _ => {}
                     }
                 }
             }
         }
 
-        'outer: for dep in deps {
-            //eprintln!("Search for {:#?}", dep);
-            for lpath in lpaths.iter() {
-                let joined = PathBuf::from(lpath).join(&dep);
-                let joined = self.canonicalize_dir(&joined).unwrap_or(joined);
-                //eprintln!("Checking {:#?}", joined);
+        let has_runpath = runpath.is_some() && !musl;
+        // musl inherits the DT_RUNPATH as well and prefers it over the DT_RPATH
+        let (search_path, rpath_chain) = if musl {
+            search_paths(runpath.unwrap_or(rpath), None, &item.rpath)
+        } else {
+            search_paths(rpath, runpath, &item.rpath)
+        };
+        let header = elf.header.clone();
+        let musl_path = match interp {
+            Some(ref i) if musl => Some(self.musl_search_path(i)),
+            _ => None,
+        };
 
-                if visited.write().unwrap().insert(joined.clone().into()) {
-                    let mut dest = self.dest_path.clone();
-                    dest.push(joined.as_os_str());
-                    let dest = PathBuf::from(dest);
-                    if joined.exists() && !dest.exists() {
-                        handle.enqueue((joined.into(), lpaths.clone()));
-                        continue 'outer;
-                    }
-                } else {
-                    continue 'outer;
-                }
-            }
+        let lookup = Lookup {
+            handle: &handle,
+            visited,
+            rpath_chain: &rpath_chain,
+            runpath: has_runpath,
+            secure,
+            requester: path,
+            header: &header,
+            interp: interp.as_ref(),
+            musl_path: musl_path.as_deref(),
+        };
 
-            if let Some(ld_so_cache) = self.ld_so_cache {
-                if let Some(vals) = ld_so_cache.get(dep.as_os_str()) {
-                    for f in vals {
-                        //eprintln!("LD_SO_CACHE Found {:#?}", val);
-                        let joined = PathBuf::from(f);
-                        let joined = self.canonicalize_dir(&joined).unwrap_or(joined);
-                        //eprintln!("Checking {:#?}", joined);
+        let mut errors: Vec<LddError> = Vec::new();
+        let mut resolved: Vec<OsString> = Vec::with_capacity(deps.len());
 
-                        if visited.write().unwrap().insert(joined.clone().into()) {
-                            let mut dest = self.dest_path.clone();
-                            dest.push(joined.as_os_str());
-                            let dest = PathBuf::from(dest);
+        for dep in deps {
+            let mut search = Search::new(self.trace);
+            let found = self.resolve(&lookup, &dep, &search_path, nodeflib, &mut search);
+            self.record_trace(path, &dep, false, &found, &mut search);
+            let found = match found {
+                Some(found) => found,
+                None => {
+                    errors.push(search.into_error(path, &dep));
+                    continue;
+                }
+            };
 
-                            if joined.exists() && !dest.exists() {
-                                handle.enqueue((joined.into(), lpaths.clone()));
+            // musl does not support symbol versioning
+            if let Some(need) = verneed.iter().find(|n| n.file == dep.as_bytes() && !musl) {
+                self.check_versions(path, need, &found, &mut errors);
+            }
+            resolved.push(found.into());
+        }
+
+        let mut dlopened: Vec<OsString> = Vec::new();
+        if let Some(threshold) = self.dlopen_priority {
+            match elf.notes(&mut f) {
+                Ok(notes) => {
+                    for note in notes {
+                        if let NoteContent::FdoDlopen(features) = note.content {
+                            for feature in features.iter().filter(|f| f.priority <= threshold) {
+                                match self.resolve_dlopen(&lookup, feature, &search_path, nodeflib)
+                                {
+                                    Ok(found) => dlopened.push(found.into()),
+                                    Err(searched) => errors.push(LddError::MissingDlopenFeature {
+                                        requester: path.into(),
+                                        feature: feature.clone(),
+                                        search_path: searched,
+                                    }),
+                                }
                             }
                         }
                     }
-                    continue 'outer;
+                }
+                Err(e) => errors.extend(malformed(e)),
+            }
+        }
+
+        self.dependencies
+            .write()
+            .unwrap()
+            .insert(path.to_os_string(), resolved);
+        if !dlopened.is_empty() {
+            self.dlopen_dependencies
+                .write()
+                .unwrap()
+                .insert(path.to_os_string(), dlopened);
+        }
+
+        errors.extend(refused.into_iter().map(|error| LddError::RefusedToken {
+            requester: path.into(),
+            error,
+        }));
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(())
+    }
+
+    /// Search the alternative sonames of a `dlopen()` feature,
+    /// until one of them is found.
+    ///
+    /// Returns all directories searched, if none was found.
+    fn resolve_dlopen(
+        &self,
+        lookup: &Lookup,
+        feature: &DlopenFeature,
+        search_path: &[OsString],
+        nodeflib: bool,
+    ) -> Result<PathBuf, Vec<OsString>> {
+        let mut searched = Vec::new();
+        for soname in feature.soname.iter() {
+            let soname = OsStr::new(soname);
+            let mut search = Search::new(self.trace);
+            let found = self.resolve(lookup, soname, search_path, nodeflib, &mut search);
+            self.record_trace(lookup.requester, soname, true, &found, &mut search);
+            if let Some(found) = found {
+                return Ok(found);
+            }
+            for dir in search.searched {
+                if !searched.contains(&dir) {
+                    searched.push(dir);
+                }
+            }
+        }
+        Err(searched)
+    }
+
+    fn record_trace(
+        &self,
+        requester: &OsStr,
+        dependency: &OsStr,
+        dlopen: bool,
+        found: &Option<PathBuf>,
+        search: &mut Search,
+    ) {
+        if let Some(steps) = search.steps.take() {
+            self.traces.write().unwrap().push(Trace {
+                requester: requester.into(),
+                dependency: dependency.into(),
+                dlopen,
+                steps,
+                chosen: found.as_ref().map(|f| f.as_os_str().into()),
+            });
+        }
+    }
+
+    /// Search the library `dep` and return the path it was found at.
+    ///
+    /// The searched directories and candidates are recorded in `search`.
+    fn resolve(
+        &self,
+        lookup: &Lookup,
+        dep: &OsStr,
+        search_path: &[OsString],
+        nodeflib: bool,
+        search: &mut Search,
+    ) -> Option<PathBuf> {
+        // A dependency with a slash is loaded as is, without searching
+        if dep.as_bytes().contains(&b'/') {
+            search.source = TraceSource::Direct;
+            return self.try_candidate(lookup, PathBuf::from(dep), search);
+        }
+
+        let try_dirs = |dirs: &[OsString], source: TraceSource, search: &mut Search| {
+            search.source = source;
+            for lpath in dirs.iter() {
+                search.searching(lpath);
+                let joined = PathBuf::from(lpath).join(dep);
+                if let Some(found) = self.try_candidate(lookup, joined, search) {
+                    return Some(found);
                 }
             }
+            None
+        };
+
+        let source = if lookup.runpath {
+            TraceSource::Runpath
+        } else {
+            TraceSource::Rpath
+        };
+        if let Some(found) = try_dirs(search_path, source, search) {
+            return Some(found);
+        }
 
-            for lpath in self.default_libdir.iter() {
-                let joined = PathBuf::from(lpath).join(&dep);
-                let joined = self.canonicalize_dir(&joined).unwrap_or(joined);
+        if let Some(musl_path) = lookup.musl_path {
+            return try_dirs(musl_path, TraceSource::MuslPath, search);
+        }
 
-                //eprintln!("Checking {:#?}", joined);
+        if nodeflib {
+            return None;
+        }
 
-                if visited.write().unwrap().insert(joined.clone().into()) {
-                    let mut dest = self.dest_path.clone();
-                    dest.push(&joined);
-                    let dest = PathBuf::from(dest);
-                    if joined.exists() && !dest.exists() {
-                        //eprintln!("Standard LIBPATH Found {:#?}", joined);
-                        handle.enqueue((joined.into(), lpaths.clone()));
-                        continue 'outer;
+        if let Some(ld_so_cache) = self.ld_so_cache {
+            if let Some(vals) = ld_so_cache.get(dep) {
+                search.source = TraceSource::LdSoCache;
+                let mut found = None;
+                for f in vals {
+                    if let Some(dir) = Path::new(f).parent() {
+                        search.searching(dir.as_os_str());
+                    }
+                    if let Some(f) = self.try_candidate(lookup, PathBuf::from(f), search) {
+                        found.get_or_insert(f);
                     }
-                } else {
-                    continue 'outer;
                 }
+                if found.is_some() {
+                    return found;
+                }
+            }
+        } else if let Some(found) = try_dirs(&self.ld_so_conf, TraceSource::LdSoConf, search) {
+            return Some(found);
+        }
+
+        try_dirs(self.default_libdir, TraceSource::DefaultLibdir, search)
+    }
+
+    /// Check, if `candidate` exists and enqueue it, if it was not seen before
+    /// and is not yet installed in the destination directory.
+    ///
+    /// Like `ld.so`, candidates built for another class, machine, byte order
+    /// or OS ABI than the requesting object are skipped.
+    ///
+    /// Returns the canonicalized path, if the dependency is satisfied by `candidate`.
+    /// Skipped incompatible candidates are recorded in `search`.
+    fn try_candidate(
+        &self,
+        lookup: &Lookup,
+        candidate: PathBuf,
+        search: &mut Search,
+    ) -> Option<PathBuf> {
+        let candidate = self.canonicalize_dir(&candidate).unwrap_or(candidate);
+
+        let host_candidate = self.host_path(&candidate);
+        if !host_candidate.exists() {
+            search.step(&candidate, Some(Rejection::NotFound));
+            return None;
+        }
+
+        let header = match File::open(&host_candidate)
+            .map_err(elfkit::Error::from)
+            .and_then(|mut f| Header::from_reader(&mut f))
+        {
+            Ok(h) => h,
+            Err(e) => {
+                debug!(
+                    self.logger,
+                    "{:?}: skipping {:?}: {:?}", lookup.requester, candidate, e
+                );
+                search.step(&candidate, Some(Rejection::Unreadable(format!("{:?}", e))));
+                return None;
             }
+        };
 
-            return Err(format!("unable to find dependency {:#?} in {:?}", dep, lpaths).into());
+        if !compatible(lookup.header, &header) {
+            debug!(
+                self.logger,
+                "{:?}: skipping incompatible {:?} ({:?} {:?} {:?} {:?})",
+                lookup.requester,
+                candidate,
+                header.ident_class,
+                header.machine,
+                header.ident_endianness,
+                header.ident_abi
+            );
+            search.step(&candidate, Some(Rejection::Incompatible(header)));
+            search.incompatible.push(candidate.into_os_string());
+            return None;
         }
-        //eprintln!("{:#?}", out);
-        Ok(())
+        search.step(&candidate, None);
+
+        if lookup
+            .visited
+            .write()
+            .unwrap()
+            .insert(candidate.clone().into())
+        {
+            let installed = match self.dest_path {
+                Some(ref dest) => {
+                    let mut dest = dest.clone();
+                    dest.push(candidate.as_os_str());
+                    PathBuf::from(dest).exists()
+                }
+                None => false,
+            };
+            if !installed {
+                lookup.handle.enqueue(LddQueueItem {
+                    path: candidate.clone().into(),
+                    rpath: lookup.rpath_chain.to_vec(),
+                    secure: lookup.secure,
+                    interp: lookup.interp.cloned(),
+                });
+            }
+        }
+        Some(candidate)
     }
 
+    /// Check, that `found` defines all non-weak versions `path` requires from it.
+    ///
+    /// Like `ld.so`, libraries without any version definitions are accepted.
+    fn check_versions(
+        &self,
+        path: &OsStr,
+        need: &VersionNeed,
+        found: &Path,
+        errors: &mut Vec<LddError>,
+    ) {
+        let defined = match self.version_definitions(found) {
+            Some(d) => d,
+            None => return,
+        };
+
+        for version in need.versions.iter() {
+            if version.flags.contains(elfkit::types::VersionFlags::WEAK) {
+                continue;
+            }
+            if !defined.contains(&version.name) {
+                errors.push(LddError::MissingVersion {
+                    requester: path.into(),
+                    dependency: OsString::from_vec(need.file.clone()),
+                    library: found.as_os_str().into(),
+                    version: OsString::from_vec(version.name.clone()),
+                });
+            }
+        }
+    }
+
+    /// The names of all versions defined by the object at `path`,
+    /// or `None`, if it has no version definitions.
+    pub fn version_definitions(&self, path: &Path) -> Option<HashSet<Vec<u8>>> {
+        {
+            if let Some(val) = self.verdef_cache.read().unwrap().get(path.as_os_str()) {
+                return val.clone();
+            }
+        }
+
+        let val = File::open(self.host_path(path)).ok().and_then(|mut f| {
+            let mut elf = Elf::from_reader(&mut f).ok()?;
+            let mut defined: Option<HashSet<Vec<u8>>> = None;
+            for shndx in 0..elf.sections.len() {
+                if elf.sections[shndx].header.shtype == elfkit::types::SectionType::GNU_VERDEF {
+                    elf.load(shndx, &mut f).ok()?;
+                    let verdef = elf.sections[shndx].content.as_verdef()?;
+                    defined
+                        .get_or_insert_with(HashSet::new)
+                        .extend(verdef.iter().filter_map(|v| v.name()).map(<[u8]>::to_vec));
+                }
+            }
+            defined
+        });
+
+        {
+            self.verdef_cache
+                .write()
+                .unwrap()
+                .insert(path.as_os_str().into(), val.clone());
+        }
+        val
+    }
+
+    /// The system search path of the musl loader `interp`
+    fn musl_search_path(&self, interp: &OsStr) -> Vec<OsString> {
+        if let Some(val) = self.musl_path_cache.read().unwrap().get(interp) {
+            return val.clone();
+        }
+        let val = musl_search_path(&self.sysroot, interp.as_bytes());
+        self.musl_path_cache
+            .write()
+            .unwrap()
+            .insert(interp.into(), val.clone());
+        val
+    }
+
+    /// The path of `path` in the host file system
     #[inline]
+    pub fn host_path(&self, path: &Path) -> PathBuf {
+        in_root(&self.sysroot, path)
+    }
+
+    #[inline]
+    #[allow(clippy::result_unit_err)]
     pub fn canonicalize_dir(&self, path: &Path) -> std::result::Result<PathBuf, ()> {
         let source_filename = path.file_name().ok_or(())?;
         let dirname = path.parent().ok_or(())?;
@@ -205,6 +853,7 @@ impl<'a> Ldd<'a> {
         Ok(canon_dirname)
     }
 
+    /// Canonicalize `path` inside [`Ldd::sysroot`] and cache the result
     #[inline]
     pub fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
         {
@@ -212,7 +861,7 @@ impl<'a> Ldd<'a> {
                 return Ok(PathBuf::from(val));
             }
         }
-        let val = path.canonicalize()?;
+        let val = canonicalize_in(&self.sysroot, path)?;
         {
             self.canon_cache
                 .write()
@@ -225,20 +874,69 @@ impl<'a> Ldd<'a> {
 
 #[cfg(test)]
 mod test {
-    use super::replace_slice;
+    use super::{compatible, search_paths};
+    use crate::elfkit::types::{Abi, Class, Endianness, Machine};
+    use crate::elfkit::Header;
+    use std::ffi::OsString;
+
+    #[test]
+    fn test_search_paths() {
+        let os = |v: &[&str]| v.iter().map(OsString::from).collect::<Vec<_>>();
+
+        // DT_RPATH is searched before the inherited DT_RPATH and passed on
+        let (search, chain) = search_paths(os(&["/a"]), None, &os(&["/exe", "/a"]));
+        assert_eq!(search, os(&["/a", "/exe"]));
+        assert_eq!(chain, os(&["/a", "/exe"]));
+
+        // DT_RUNPATH disables all DT_RPATHs and is not inherited
+        let (search, chain) = search_paths(os(&["/a"]), Some(os(&["/b"])), &os(&["/exe"]));
+        assert_eq!(search, os(&["/b"]));
+        assert_eq!(chain, os(&["/exe"]));
+
+        // an empty DT_RUNPATH still disables DT_RPATH
+        let (search, chain) = search_paths(os(&["/a"]), Some(vec![]), &[]);
+        assert!(search.is_empty());
+        assert!(chain.is_empty());
+    }
 
     #[test]
-    fn test_replace_slice() {
-        assert_eq!(replace_slice(b"test", b"$ORIGIN", b"TEST"), b"test");
-        assert_eq!(replace_slice(b"$ORIGIN", b"$ORIGIN", b"TEST"), b"TEST");
-        assert_eq!(replace_slice(b"/$ORIGIN/", b"$ORIGIN", b"TEST"), b"/TEST/");
-        assert_eq!(
-            replace_slice(b"/_ORIGIN/", b"$ORIGIN", b"TEST"),
-            b"/_ORIGIN/"
-        );
-        assert_eq!(
-            replace_slice(b"/_ORIGIN//", b"$ORIGIN", b"TEST"),
-            b"/_ORIGIN//"
-        );
+    fn test_compatible() {
+        let x86_64 = Header {
+            machine: Machine::X86_64,
+            ..Default::default()
+        };
+        assert!(compatible(&x86_64, &x86_64));
+
+        let i686 = Header {
+            ident_class: Class::Class32,
+            machine: Machine::EM386,
+            ..Default::default()
+        };
+        assert!(!compatible(&x86_64, &i686));
+
+        let x32 = Header {
+            ident_class: Class::Class32,
+            ..x86_64.clone()
+        };
+        assert!(!compatible(&x86_64, &x32));
+
+        let big_endian = Header {
+            ident_endianness: Endianness::BigEndian,
+            ..x86_64.clone()
+        };
+        assert!(!compatible(&x86_64, &big_endian));
+
+        let gnu = Header {
+            ident_abi: Abi::GNU,
+            ..x86_64.clone()
+        };
+        assert!(compatible(&x86_64, &gnu));
+        assert!(compatible(&gnu, &x86_64));
+
+        let freebsd = Header {
+            ident_abi: Abi::FREEBSD,
+            ..x86_64.clone()
+        };
+        assert!(!compatible(&x86_64, &freebsd));
     }
 }
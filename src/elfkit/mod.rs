@@ -1,21 +1,34 @@
 #[macro_use]
 mod utils;
 pub mod dl_cache;
+pub mod dst;
 pub mod dynamic;
 pub mod elf;
 pub mod error;
+pub mod explain;
+pub mod hash;
 pub mod header;
 pub mod ld_so_cache;
+pub mod ld_so_conf;
 pub mod ldd;
+pub mod musl;
+pub mod note;
 pub mod section;
 pub mod segment;
+pub mod strip;
 pub mod strtab;
+pub mod symbol;
+pub mod symcheck;
 pub mod types;
+pub mod version;
 
 pub use dynamic::{Dynamic, DynamicContent};
 pub use elf::Elf;
 pub use error::Error;
 pub use header::Header;
+pub use note::{Note, NoteContent};
 pub use section::{Section, SectionContent, SectionHeader};
 pub use segment::SegmentHeader;
 pub use strtab::Strtab;
+pub use symbol::{Symbol, SymbolSectionIndex};
+pub use version::VersionNeed;
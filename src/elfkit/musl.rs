@@ -0,0 +1,131 @@
+//! The library search of the musl dynamic linker
+//!
+//! musl has no `ld.so.cache` and no `DF_1_NODEFLIB`. A library is searched in
+//! * the `DT_RUNPATH` or else `DT_RPATH` of the object needing it and of all its loaders
+//! * the directories of `/etc/ld-musl-$ARCH.path`, relative to the prefix of the loader,
+//!   or `/lib:/usr/local/lib:/usr/lib`, if the file does not exist
+//!
+//! Only `$ORIGIN` is expanded, a search path with any other token is ignored.
+
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::os::unix::prelude::*;
+use std::path::Path;
+
+use crate::file::in_root;
+
+/// The search path of musl, if no `/etc/ld-musl-$ARCH.path` exists
+pub const MUSL_DEFAULT_PATH: &[&str] = &["/lib", "/usr/local/lib", "/usr/lib"];
+
+/// The `$ARCH` of a musl program interpreter like `/lib/ld-musl-x86_64.so.1`,
+/// or `None`, if `interp` is not the musl loader.
+pub fn musl_arch(interp: &[u8]) -> Option<&[u8]> {
+    let name = Path::new(OsStr::from_bytes(interp)).file_name()?.as_bytes();
+    let arch = name.strip_prefix(b"ld-musl-")?;
+    let end = arch.windows(3).position(|w| w == b".so")?;
+    Some(&arch[..end])
+}
+
+/// Read the system search path of the musl loader `interp` inside `sysroot`.
+pub fn musl_search_path(sysroot: &Path, interp: &[u8]) -> Vec<OsString> {
+    let arch = match musl_arch(interp) {
+        Some(a) => a,
+        None => return Vec::new(),
+    };
+
+    // the loader's directory is expected in "$prefix/lib"
+    let prefix = Path::new(OsStr::from_bytes(interp))
+        .parent()
+        .and_then(Path::parent)
+        .unwrap_or_else(|| Path::new("/"));
+    let mut name = OsString::from("etc/ld-musl-");
+    name.push(OsStr::from_bytes(arch));
+    name.push(".path");
+
+    match fs::read(in_root(sysroot, &prefix.join(name))) {
+        Ok(content) => content
+            .split(|b| *b == 0)
+            .next()
+            .unwrap_or_default()
+            .split(|b| *b == b':' || *b == b'\n')
+            .filter(|p| !p.is_empty())
+            .map(|p| OsString::from_vec(p.to_vec()))
+            .collect(),
+        Err(_) => MUSL_DEFAULT_PATH.iter().map(OsString::from).collect(),
+    }
+}
+
+/// Check, if musl expands the dynamic string tokens of a `DT_RPATH` or `DT_RUNPATH` value
+pub fn musl_expands(value: &[u8]) -> bool {
+    value
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| **b == b'$')
+        .all(|(i, _)| value[i..].starts_with(b"$ORIGIN") || value[i..].starts_with(b"${ORIGIN}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{musl_arch, musl_expands, musl_search_path};
+    use std::ffi::OsString;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_musl_arch() {
+        assert_eq!(musl_arch(b"/lib/ld-musl-x86_64.so.1"), Some(&b"x86_64"[..]));
+        assert_eq!(
+            musl_arch(b"/lib/ld-musl-mips-sf.so.1"),
+            Some(&b"mips-sf"[..])
+        );
+        assert_eq!(musl_arch(b"/lib64/ld-linux-x86-64.so.2"), None);
+    }
+
+    #[test]
+    fn test_musl_expands() {
+        assert!(musl_expands(b"/usr/lib"));
+        assert!(musl_expands(b"$ORIGIN/../lib:${ORIGIN}/lib"));
+        assert!(!musl_expands(b"$ORIGIN/../$LIB"));
+        assert!(!musl_expands(b"/usr/$PLATFORM"));
+    }
+
+    #[test]
+    fn test_musl_search_path() {
+        let os = |v: &[&str]| v.iter().map(OsString::from).collect::<Vec<_>>();
+        let tmp_dir = TempDir::new().unwrap();
+        let root = tmp_dir.path();
+        let interp = b"/lib/ld-musl-x86_64.so.1";
+
+        assert_eq!(
+            musl_search_path(root, interp),
+            os(&["/lib", "/usr/local/lib", "/usr/lib"])
+        );
+
+        fs::create_dir_all(root.join("etc")).unwrap();
+        fs::write(
+            root.join("etc/ld-musl-x86_64.path"),
+            "/lib\n/usr/lib:/opt/lib\n\n",
+        )
+        .unwrap();
+        assert_eq!(
+            musl_search_path(root, interp),
+            os(&["/lib", "/usr/lib", "/opt/lib"])
+        );
+
+        // an empty path file disables the default directories
+        fs::write(root.join("etc/ld-musl-x86_64.path"), "").unwrap();
+        assert!(musl_search_path(root, interp).is_empty());
+
+        // the path file is relative to the loader prefix
+        fs::create_dir_all(root.join("opt/musl/etc")).unwrap();
+        fs::write(
+            root.join("opt/musl/etc/ld-musl-x86_64.path"),
+            "/opt/musl/lib",
+        )
+        .unwrap();
+        assert_eq!(
+            musl_search_path(root, b"/opt/musl/lib/ld-musl-x86_64.so.1"),
+            os(&["/opt/musl/lib"])
+        );
+    }
+}
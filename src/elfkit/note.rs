@@ -0,0 +1,365 @@
+//! ELF notes from `SHT_NOTE` sections and `PT_NOTE` segments
+//!
+//! Known notes are decoded into a [`NoteContent`], all others are kept as raw bytes.
+//! See <https://systemd.io/ELF_DLOPEN_METADATA/> for the `FDO_DLOPEN` note.
+
+use serde::{Deserialize, Serialize};
+
+use super::types;
+use super::{Error, Header, SectionContent};
+use std::io::Read;
+
+/// `NT_GNU_ABI_TAG` of the "GNU" owner
+pub const NT_GNU_ABI_TAG: u32 = 1;
+/// `NT_GNU_BUILD_ID` of the "GNU" owner
+pub const NT_GNU_BUILD_ID: u32 = 3;
+/// `NT_GNU_PROPERTY_TYPE_0` of the "GNU" owner
+pub const NT_GNU_PROPERTY_TYPE_0: u32 = 5;
+/// `NT_FDO_PACKAGING_METADATA` of the "FDO" owner
+pub const NT_FDO_PACKAGING_METADATA: u32 = 0xcafe_1a7e;
+/// `NT_FDO_DLOPEN_METADATA` of the "FDO" owner
+pub const NT_FDO_DLOPEN_METADATA: u32 = 0x407c_0c0a;
+
+/// How important an optional `dlopen()` dependency is for the object
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum DlopenPriority {
+    /// the core functionality does not work without it
+    Required,
+    /// important functionality is missing without it
+    #[default]
+    Recommended,
+    /// only some optional functionality is missing without it
+    Suggested,
+}
+
+impl std::fmt::Display for DlopenPriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            DlopenPriority::Required => "required",
+            DlopenPriority::Recommended => "recommended",
+            DlopenPriority::Suggested => "suggested",
+        })
+    }
+}
+
+impl std::str::FromStr for DlopenPriority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "required" => Ok(DlopenPriority::Required),
+            "recommended" => Ok(DlopenPriority::Recommended),
+            "suggested" => Ok(DlopenPriority::Suggested),
+            _ => Err(format!("unknown dlopen priority {:?}", s)),
+        }
+    }
+}
+
+/// A feature of an object, which is provided by libraries loaded with `dlopen()`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DlopenFeature {
+    #[serde(default)]
+    pub feature: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub priority: DlopenPriority,
+    /// alternative sonames, any one of them provides the feature
+    pub soname: Vec<String>,
+}
+
+/// An entry of a `NT_GNU_PROPERTY_TYPE_0` note
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GnuProperty {
+    pub prtype: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NoteContent {
+    /// the minimal kernel ABI an object requires
+    GnuAbiTag {
+        os: u32,
+        major: u32,
+        minor: u32,
+        patch: u32,
+    },
+    GnuBuildId(Vec<u8>),
+    GnuProperty(Vec<GnuProperty>),
+    FdoDlopen(Vec<DlopenFeature>),
+    /// the package the object was built in, e.g. `{"type":"rpm","name":"systemd",...}`
+    FdoPackage(serde_json::Value),
+    /// unknown or malformed notes
+    Raw(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Note {
+    /// the owner of the note without the terminating NUL
+    pub name: Vec<u8>,
+    pub ntype: u32,
+    pub content: NoteContent,
+}
+
+impl Note {
+    /// Read the notes of a `SHT_NOTE` section or a `PT_NOTE` segment aligned to `align`
+    pub fn from_reader<R>(
+        mut io: R,
+        _linked: Option<&SectionContent>,
+        eh: &Header,
+        align: u64,
+    ) -> Result<SectionContent, Error>
+    where
+        R: Read,
+    {
+        let mut data = Vec::new();
+        io.read_to_end(&mut data)?;
+        Ok(SectionContent::Notes(Note::parse(&data, eh, align)?))
+    }
+
+    pub fn parse(data: &[u8], eh: &Header, align: u64) -> Result<Vec<Note>, Error> {
+        let align = if align == 8 { 8 } else { 4 };
+        let mut r = Vec::new();
+        let mut offset = 0usize;
+
+        while offset + 12 <= data.len() {
+            let mut io = &data[offset..];
+            let (namesz, descsz, ntype) = elf_dispatch_endianness!(eh => {
+                let namesz = read_u32(&mut io)? as usize;
+                let descsz = read_u32(&mut io)? as usize;
+                let ntype = read_u32(&mut io)?;
+                (namesz, descsz, ntype)
+            });
+            offset += 12;
+
+            let name = data
+                .get(offset..offset + namesz)
+                .ok_or(Error::UnexpectedSectionContent)?;
+            let name = name.split(|c| *c == 0).next().unwrap_or_default().to_vec();
+            offset = align_up(offset + namesz, align);
+
+            let desc = data
+                .get(offset..offset + descsz)
+                .ok_or(Error::UnexpectedSectionContent)?;
+            offset = align_up(offset + descsz, align);
+
+            let content = NoteContent::parse(&name, ntype, desc, eh)?;
+            r.push(Note {
+                name,
+                ntype,
+                content,
+            });
+        }
+
+        Ok(r)
+    }
+}
+
+impl NoteContent {
+    fn parse(name: &[u8], ntype: u32, desc: &[u8], eh: &Header) -> Result<NoteContent, Error> {
+        let content = match (name, ntype) {
+            (b"GNU", NT_GNU_ABI_TAG) if desc.len() >= 16 => {
+                let mut io = desc;
+                elf_dispatch_endianness!(eh => {
+                    NoteContent::GnuAbiTag {
+                        os: read_u32(&mut io)?,
+                        major: read_u32(&mut io)?,
+                        minor: read_u32(&mut io)?,
+                        patch: read_u32(&mut io)?,
+                    }
+                })
+            }
+            (b"GNU", NT_GNU_BUILD_ID) => NoteContent::GnuBuildId(desc.to_vec()),
+            (b"GNU", NT_GNU_PROPERTY_TYPE_0) => {
+                let align = match eh.ident_class {
+                    types::Class::Class32 => 4,
+                    types::Class::Class64 => 8,
+                };
+                let mut properties = Vec::new();
+                let mut offset = 0usize;
+                while offset + 8 <= desc.len() {
+                    let mut io = &desc[offset..];
+                    let (prtype, datasz) = elf_dispatch_endianness!(eh => {
+                        (read_u32(&mut io)?, read_u32(&mut io)? as usize)
+                    });
+                    offset += 8;
+                    let data = desc
+                        .get(offset..offset + datasz)
+                        .ok_or(Error::UnexpectedSectionContent)?;
+                    properties.push(GnuProperty {
+                        prtype,
+                        data: data.to_vec(),
+                    });
+                    offset += align_up(datasz, align);
+                }
+                NoteContent::GnuProperty(properties)
+            }
+            (b"FDO", NT_FDO_DLOPEN_METADATA) => match serde_json::from_slice(json_desc(desc)) {
+                Ok(features) => NoteContent::FdoDlopen(features),
+                Err(_) => NoteContent::Raw(desc.to_vec()),
+            },
+            (b"FDO", NT_FDO_PACKAGING_METADATA) => match serde_json::from_slice(json_desc(desc)) {
+                Ok(package) => NoteContent::FdoPackage(package),
+                Err(_) => NoteContent::Raw(desc.to_vec()),
+            },
+            _ => NoteContent::Raw(desc.to_vec()),
+        };
+        Ok(content)
+    }
+}
+
+/// The JSON of the "FDO" notes is NUL terminated and padded
+fn json_desc(desc: &[u8]) -> &[u8] {
+    desc.split(|c| *c == 0).next().unwrap_or_default()
+}
+
+fn align_up(v: usize, align: usize) -> usize {
+    (v + align - 1) & !(align - 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DlopenFeature, DlopenPriority, GnuProperty, Note, NoteContent};
+    use crate::elfkit::Header;
+
+    fn note(name: &[u8], ntype: u32, desc: &[u8], align: usize) -> Vec<u8> {
+        let mut b = Vec::new();
+        b.extend_from_slice(&(name.len() as u32 + 1).to_le_bytes());
+        b.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+        b.extend_from_slice(&ntype.to_le_bytes());
+        b.extend_from_slice(name);
+        b.push(0);
+        while b.len() % align != 0 {
+            b.push(0);
+        }
+        b.extend_from_slice(desc);
+        while b.len() % align != 0 {
+            b.push(0);
+        }
+        b
+    }
+
+    #[test]
+    fn test_parse_notes() {
+        let eh = Header::default();
+        let mut data = note(b"GNU", 3, &[0xde, 0xad, 0xbe, 0xef, 0x01], 4);
+        data.extend(note(
+            b"GNU",
+            1,
+            &[0, 0, 0, 0, 3, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0],
+            4,
+        ));
+        data.extend(note(
+            b"FDO",
+            0x407c_0c0a,
+            br#"[{"feature":"tpm","description":"TPM2 support","priority":"suggested","soname":["libtss2-esys.so.0"]},{"soname":["libfido2.so.1"]}]"#,
+            4,
+        ));
+        data.extend(note(b"XYZ", 42, b"abc", 4));
+
+        let notes = Note::parse(&data, &eh, 4).unwrap();
+        assert_eq!(notes.len(), 4);
+        assert_eq!(
+            notes[0].content,
+            NoteContent::GnuBuildId(vec![0xde, 0xad, 0xbe, 0xef, 0x01])
+        );
+        assert_eq!(
+            notes[1].content,
+            NoteContent::GnuAbiTag {
+                os: 0,
+                major: 3,
+                minor: 2,
+                patch: 0
+            }
+        );
+        assert_eq!(
+            notes[2].content,
+            NoteContent::FdoDlopen(vec![
+                DlopenFeature {
+                    feature: Some("tpm".into()),
+                    description: Some("TPM2 support".into()),
+                    priority: DlopenPriority::Suggested,
+                    soname: vec!["libtss2-esys.so.0".into()],
+                },
+                DlopenFeature {
+                    priority: DlopenPriority::Recommended,
+                    soname: vec!["libfido2.so.1".into()],
+                    ..Default::default()
+                }
+            ])
+        );
+        assert_eq!(notes[3].name, b"XYZ");
+        assert_eq!(notes[3].content, NoteContent::Raw(b"abc".to_vec()));
+    }
+
+    #[test]
+    fn test_dlopen_priority() {
+        assert!(DlopenPriority::Required < DlopenPriority::Recommended);
+        assert!(DlopenPriority::Recommended < DlopenPriority::Suggested);
+        assert_eq!("suggested".parse(), Ok(DlopenPriority::Suggested));
+        assert!("optional".parse::<DlopenPriority>().is_err());
+        assert_eq!(DlopenPriority::Required.to_string(), "required");
+    }
+
+    #[test]
+    fn test_parse_gnu_property() {
+        let eh = Header::default();
+        // GNU_PROPERTY_X86_FEATURE_1_AND: IBT | SHSTK
+        let desc = [0x02, 0x00, 0x00, 0xc0, 4, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0];
+        let mut data = note(b"GNU", 5, &desc, 8);
+        data.extend(note(b"GNU", 3, &[1, 2, 3], 8));
+
+        let notes = Note::parse(&data, &eh, 8).unwrap();
+        assert_eq!(
+            notes[0].content,
+            NoteContent::GnuProperty(vec![GnuProperty {
+                prtype: 0xc000_0002,
+                data: vec![3, 0, 0, 0],
+            }])
+        );
+        assert_eq!(notes[1].content, NoteContent::GnuBuildId(vec![1, 2, 3]));
+    }
+
+    #[cfg(target_env = "gnu")]
+    #[test]
+    fn test_build_id() {
+        let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+        let libc = maps
+            .lines()
+            .filter_map(|l| l.split_whitespace().nth(5))
+            .find(|p| p.contains("/libc.so") || p.contains("/libc-"))
+            .unwrap();
+
+        let mut f = std::fs::File::open(libc).unwrap();
+        let mut elf = crate::elfkit::Elf::from_reader(&mut f).unwrap();
+        let notes = elf.notes(&mut f).unwrap();
+        let ids = notes
+            .iter()
+            .filter_map(|n| match n.content {
+                NoteContent::GnuBuildId(ref id) => Some(id),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ids.len(), 1);
+        // the SHA-1 build-id of the distribution libc
+        assert_eq!(ids[0].len(), 20);
+
+        // like `readelf -n`, the descriptor follows the 12 byte header and "GNU\0"
+        let section = elf
+            .sections
+            .iter()
+            .find(|s| s.name == b".note.gnu.build-id")
+            .unwrap();
+        let data = std::fs::read(libc).unwrap();
+        let start = section.header.offset as usize;
+        let raw = &data[start..start + section.header.size as usize];
+        assert_eq!(&raw[..4], &4u32.to_ne_bytes());
+        assert_eq!(&raw[4..8], &20u32.to_ne_bytes());
+        assert_eq!(&raw[8..12], &3u32.to_ne_bytes());
+        assert_eq!(&raw[12..16], b"GNU\0");
+        assert_eq!(&raw[16..], ids[0].as_slice());
+    }
+}
@@ -1,10 +1,14 @@
 use super::dynamic::Dynamic;
 use super::error::Error;
+use super::hash::{GnuHash, SysvHash};
 use super::header::Header;
+use super::note::Note;
 use super::strtab::Strtab;
+use super::symbol::Symbol;
 use super::types;
+use super::version::{VersionDefinition, VersionNeed};
 
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Read, Seek, SeekFrom, Write};
 
 #[derive(Default, Debug, Clone)]
 pub struct SectionHeader {
@@ -52,6 +56,23 @@ impl SectionHeader {
             })
         })
     }
+
+    pub fn to_writer<W>(&self, io: &mut W, eh: &Header) -> Result<(), Error>
+    where
+        W: Write,
+    {
+        elf_write_u32!(eh, io, self.name)?;
+        elf_write_u32!(eh, io, self.shtype.0)?;
+        elf_write_uclass!(eh, io, self.flags.bits())?;
+        elf_write_uclass!(eh, io, self.addr)?;
+        elf_write_uclass!(eh, io, self.offset)?;
+        elf_write_uclass!(eh, io, self.size)?;
+        elf_write_u32!(eh, io, self.link)?;
+        elf_write_u32!(eh, io, self.info)?;
+        elf_write_uclass!(eh, io, self.addralign)?;
+        elf_write_uclass!(eh, io, self.entsize)?;
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -60,6 +81,12 @@ pub enum SectionContent {
     Unloaded,
     Dynamic(Vec<Dynamic>),
     Strtab(Strtab),
+    VerDef(Vec<VersionDefinition>),
+    VerNeed(Vec<VersionNeed>),
+    Symbols(Vec<Symbol>),
+    Hash(SysvHash),
+    GnuHash(GnuHash),
+    Notes(Vec<Note>),
 }
 
 impl Default for SectionContent {
@@ -94,6 +121,30 @@ impl SectionContent {
             _ => None,
         }
     }
+    pub fn as_verdef(&self) -> Option<&Vec<VersionDefinition>> {
+        match *self {
+            SectionContent::VerDef(ref v) => Some(v),
+            _ => None,
+        }
+    }
+    pub fn as_verneed(&self) -> Option<&Vec<VersionNeed>> {
+        match *self {
+            SectionContent::VerNeed(ref v) => Some(v),
+            _ => None,
+        }
+    }
+    pub fn as_symbols(&self) -> Option<&Vec<Symbol>> {
+        match *self {
+            SectionContent::Symbols(ref v) => Some(v),
+            _ => None,
+        }
+    }
+    pub fn as_notes(&self) -> Option<&Vec<Note>> {
+        match *self {
+            SectionContent::Notes(ref v) => Some(v),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -136,6 +187,30 @@ impl Section {
                 let io = bb.as_slice();
                 Dynamic::from_reader(io, linked, eh)?
             }
+            types::SectionType::GNU_VERDEF => {
+                let io = bb.as_slice();
+                VersionDefinition::from_reader(io, linked, eh)?
+            }
+            types::SectionType::GNU_VERNEED => {
+                let io = bb.as_slice();
+                VersionNeed::from_reader(io, linked, eh)?
+            }
+            types::SectionType::SYMTAB | types::SectionType::DYNSYM => {
+                let io = bb.as_slice();
+                Symbol::from_reader(io, linked, eh)?
+            }
+            types::SectionType::NOTE => {
+                let io = bb.as_slice();
+                Note::from_reader(io, linked, eh, self.header.addralign)?
+            }
+            types::SectionType::HASH => {
+                let io = bb.as_slice();
+                SysvHash::from_reader(io, linked, eh)?
+            }
+            types::SectionType::GNU_HASH => {
+                let io = bb.as_slice();
+                GnuHash::from_reader(io, linked, eh)?
+            }
             _ => SectionContent::Unloaded,
         };
         Ok(())
@@ -0,0 +1,282 @@
+//! Removal of debug information from ELF executables and shared libraries
+//!
+//! The non-allocated `.debug_*` and `.zdebug_*` sections, the `.symtab` with its
+//! string table and the relocations of removed sections are dropped. The loaded part
+//! of the file is copied byte for byte, the remaining sections are moved behind it,
+//! followed by a new `shstrtab` and section header table.
+
+use std::io::Cursor;
+
+use super::error::Error;
+use super::section::Section;
+use super::types;
+use super::Elf;
+
+/// The trailer of a kernel module signed by `scripts/sign-file`
+pub const MODULE_SIG_MAGIC: &[u8] = b"~Module signature appended~\n";
+
+/// Strip the debug information of the ELF object in `data`.
+///
+/// Returns `None`, if there is nothing to strip or the object has to stay untouched,
+/// like relocatable objects, kernel modules and objects with an appended signature.
+pub fn strip_debug(data: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    if data.ends_with(MODULE_SIG_MAGIC) {
+        return Ok(None);
+    }
+
+    let elf = Elf::from_reader(&mut Cursor::new(data))?;
+    let eh = &elf.header;
+    let sections = &elf.sections;
+    let shstrndx = eh.shstrndx as usize;
+
+    if !matches!(eh.etype, types::ElfType::EXEC | types::ElfType::DYN)
+        || sections.is_empty()
+        || shstrndx >= sections.len()
+    {
+        return Ok(None);
+    }
+
+    let alloc = |s: &Section| s.header.flags.contains(types::SectionFlags::ALLOC);
+    let rel = |s: &Section| {
+        s.header.shtype == types::SectionType::REL || s.header.shtype == types::SectionType::RELA
+    };
+
+    let mut remove = sections
+        .iter()
+        .map(|s| {
+            !alloc(s)
+                && (s.name.starts_with(b".debug_")
+                    || s.name.starts_with(b".zdebug_")
+                    || s.header.shtype == types::SectionType::SYMTAB)
+        })
+        .collect::<Vec<_>>();
+    remove[0] = false;
+
+    for s in sections.iter() {
+        let link = s.header.link as usize;
+        if s.header.shtype == types::SectionType::SYMTAB
+            && link != shstrndx
+            && link < sections.len()
+            && !alloc(&sections[link])
+        {
+            remove[link] = true;
+        }
+    }
+    for (i, s) in sections.iter().enumerate() {
+        let (link, info) = (s.header.link as usize, s.header.info as usize);
+        if rel(s)
+            && !alloc(s)
+            && (remove.get(info) == Some(&true) || remove.get(link) == Some(&true))
+        {
+            remove[i] = true;
+        }
+    }
+
+    let first_removed = match remove.iter().position(|r| *r) {
+        Some(i) => i,
+        None => return Ok(None),
+    };
+
+    // indices of allocated sections are referenced from the loaded image and must not change
+    if sections[first_removed..]
+        .iter()
+        .zip(remove[first_removed..].iter())
+        .any(|(s, r)| !r && alloc(s))
+    {
+        return Ok(None);
+    }
+
+    let mut index = vec![0u32; sections.len()];
+    let mut n = 0;
+    for (i, r) in remove.iter().enumerate() {
+        if !r {
+            index[i] = n;
+            n += 1;
+        }
+    }
+
+    // a kept section referencing a removed one would dangle
+    let remap = |i: u32| match remove.get(i as usize) {
+        Some(false) => Some(index[i as usize]),
+        _ => None,
+    };
+
+    // everything up to the end of the loaded sections and segments stays in place
+    let mut loaded_end = (eh.phoff + eh.phentsize as u64 * eh.phnum as u64).max(eh.ehsize as u64);
+    for seg in elf.segments.iter() {
+        loaded_end = loaded_end.max(seg.offset + seg.filesz);
+    }
+    for s in sections.iter().filter(|s| alloc(s)) {
+        if s.header.shtype != types::SectionType::NOBITS {
+            loaded_end = loaded_end.max(s.header.offset + s.header.size);
+        }
+    }
+    if loaded_end > data.len() as u64 {
+        return Ok(None);
+    }
+
+    let mut out = data[..loaded_end as usize].to_vec();
+    let mut shstrtab = vec![0u8];
+    let mut headers = Vec::with_capacity(n as usize);
+
+    for (i, s) in sections.iter().enumerate().filter(|(i, _)| !remove[*i]) {
+        let mut h = s.header.clone();
+
+        if i != 0 {
+            h.name = shstrtab.len() as u32;
+            shstrtab.extend_from_slice(&s.name);
+            shstrtab.push(0);
+
+            if h.link != 0 {
+                h.link = match remap(h.link) {
+                    Some(l) => l,
+                    None => return Ok(None),
+                };
+            }
+            if (rel(s) || h.flags.contains(types::SectionFlags::INFO_LINK)) && h.info != 0 {
+                h.info = match remap(h.info) {
+                    Some(l) => l,
+                    None => return Ok(None),
+                };
+            }
+        }
+
+        let end = h.offset + h.size;
+        if i != 0
+            && i != shstrndx
+            && !alloc(s)
+            && h.shtype != types::SectionType::NOBITS
+            && end > loaded_end
+        {
+            if end > data.len() as u64 {
+                return Ok(None);
+            }
+            align(&mut out, h.addralign);
+            let offset = out.len() as u64;
+            out.extend_from_slice(&data[h.offset as usize..end as usize]);
+            h.offset = offset;
+        }
+        headers.push(h);
+    }
+
+    let new_shstrndx = index[shstrndx] as usize;
+    headers[new_shstrndx].offset = out.len() as u64;
+    headers[new_shstrndx].size = shstrtab.len() as u64;
+    out.extend_from_slice(&shstrtab);
+
+    align(
+        &mut out,
+        match eh.ident_class {
+            types::Class::Class32 => 4,
+            types::Class::Class64 => 8,
+        },
+    );
+
+    let mut header = eh.clone();
+    header.shoff = out.len() as u64;
+    header.shnum = headers.len() as u16;
+    header.shstrndx = new_shstrndx as u16;
+    for h in headers.iter() {
+        h.to_writer(&mut out, &header)?;
+    }
+
+    // only e_shoff, e_shnum and e_shstrndx are patched, the other fields are kept
+    // byte for byte, including the e_flags bits unknown to elfkit
+    let mut ehdr = Vec::with_capacity(header.ehsize as usize);
+    header.to_writer(&mut ehdr)?;
+    let (shoff, shnum) = match eh.ident_class {
+        types::Class::Class32 => (0x20..0x24, 0x30..0x34),
+        types::Class::Class64 => (0x28..0x30, 0x3c..0x40),
+    };
+    out[shoff.clone()].copy_from_slice(&ehdr[shoff]);
+    out[shnum.clone()].copy_from_slice(&ehdr[shnum]);
+
+    Ok(Some(out))
+}
+
+fn align(out: &mut Vec<u8>, alignment: u64) {
+    if alignment > 1 {
+        let len = out.len() as u64;
+        out.resize((len.div_ceil(alignment) * alignment) as usize, 0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{strip_debug, MODULE_SIG_MAGIC};
+    use crate::elfkit::{types, Elf};
+    use std::io::Cursor;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+    use tempfile::TempDir;
+
+    /// Build a program with debug information in `dir`, `None` without a compiler
+    fn build_prog(dir: &Path) -> Option<PathBuf> {
+        let src = dir.join("prog.c");
+        let prog = dir.join("prog");
+        std::fs::write(&src, "int main(void) { return 42; }\n").unwrap();
+        match Command::new("cc")
+            .arg("-g")
+            .arg("-o")
+            .arg(&prog)
+            .arg(&src)
+            .status()
+        {
+            Ok(s) if s.success() => Some(prog),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_strip_debug() {
+        let tmp_dir = TempDir::new().unwrap();
+        let prog = match build_prog(tmp_dir.path()) {
+            Some(p) => p,
+            None => return,
+        };
+
+        let data = std::fs::read(&prog).unwrap();
+        let stripped = strip_debug(&data).unwrap().unwrap();
+        assert!(stripped.len() < data.len());
+
+        let elf = Elf::from_reader(&mut Cursor::new(&stripped)).unwrap();
+        assert!(!elf.sections.iter().any(
+            |s| s.name.starts_with(b".debug_") || s.header.shtype == types::SectionType::SYMTAB
+        ));
+        assert!(elf.sections.iter().any(|s| s.name == b".dynsym"));
+        assert!(strip_debug(&stripped).unwrap().is_none());
+
+        std::fs::write(&prog, &stripped).unwrap();
+        assert_eq!(Command::new(&prog).status().unwrap().code(), Some(42));
+
+        let mut signed = data;
+        signed.extend_from_slice(MODULE_SIG_MAGIC);
+        assert!(strip_debug(&signed).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_strip_keeps_header_flags() {
+        let tmp_dir = TempDir::new().unwrap();
+        let prog = match build_prog(tmp_dir.path()) {
+            Some(p) => p,
+            None => return,
+        };
+
+        let mut data = std::fs::read(&prog).unwrap();
+        let elf = Elf::from_reader(&mut Cursor::new(&data)).unwrap();
+        let (flags, shoff, shnum) = match elf.header.ident_class {
+            types::Class::Class32 => (0x24..0x28, 0x20..0x24, 0x30..0x34),
+            types::Class::Class64 => (0x30..0x34, 0x28..0x30, 0x3c..0x40),
+        };
+        // like EF_MIPS_ABI2 and others, which elfkit does not know
+        data[flags.clone()].copy_from_slice(&[0x20, 0x00, 0x5a, 0xa5]);
+
+        let stripped = strip_debug(&data).unwrap().unwrap();
+        let ehsize = elf.header.ehsize as usize;
+        for i in (0..ehsize).filter(|i| !shoff.contains(i) && !shnum.contains(i)) {
+            assert_eq!(stripped[i], data[i], "header byte {:#x}", i);
+        }
+        assert_eq!(stripped[flags], [0x20, 0x00, 0x5a, 0xa5]);
+        assert!(Elf::from_reader(&mut Cursor::new(&stripped)).is_ok());
+    }
+}
@@ -0,0 +1,204 @@
+use super::types;
+use super::{Error, Header, SectionContent, Strtab};
+use num_traits::FromPrimitive;
+use std::io::Read;
+
+/// The section a [`Symbol`] is defined in
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum SymbolSectionIndex {
+    /// `SHN_UNDEF`, the symbol has to be provided by another object
+    #[default]
+    Undefined,
+    /// `SHN_ABS`
+    Absolute,
+    /// `SHN_COMMON`
+    Common,
+    Section(u16),
+}
+
+impl From<u16> for SymbolSectionIndex {
+    fn from(shndx: u16) -> Self {
+        match shndx {
+            0 => SymbolSectionIndex::Undefined,
+            0xfff1 => SymbolSectionIndex::Absolute,
+            0xfff2 => SymbolSectionIndex::Common,
+            i => SymbolSectionIndex::Section(i),
+        }
+    }
+}
+
+/// An entry of a symbol table (`Elf_Sym`)
+#[derive(Debug, Clone, Default)]
+pub struct Symbol {
+    pub name: Vec<u8>,
+    pub value: u64,
+    pub size: u64,
+    pub stype: types::SymbolType,
+    pub bind: types::SymbolBind,
+    pub vis: types::SymbolVis,
+    pub shndx: SymbolSectionIndex,
+}
+
+impl Symbol {
+    /// The symbol is defined in this object and visible to other objects
+    pub fn is_exported(&self) -> bool {
+        self.shndx != SymbolSectionIndex::Undefined
+            && self.bind != types::SymbolBind::LOCAL
+            && !matches!(
+                self.vis,
+                types::SymbolVis::HIDDEN | types::SymbolVis::INTERNAL
+            )
+    }
+
+    /// The symbol has to be provided by another object
+    pub fn is_undefined(&self) -> bool {
+        self.shndx == SymbolSectionIndex::Undefined && !self.name.is_empty()
+    }
+
+    pub fn from_reader<R>(
+        mut io: R,
+        linked: Option<&SectionContent>,
+        eh: &Header,
+    ) -> Result<SectionContent, Error>
+    where
+        R: Read,
+    {
+        let strtab = match linked {
+            None => None,
+            Some(SectionContent::Strtab(s)) => Some(s),
+            any => {
+                return Err(Error::LinkedSectionIsNotStrtab {
+                    during: "reading symbols",
+                    link: any.cloned(),
+                });
+            }
+        };
+
+        let mut data = Vec::new();
+        io.read_to_end(&mut data)?;
+        let mut io = data.as_slice();
+
+        let mut r = Vec::new();
+        while !io.is_empty() {
+            r.push(Symbol::from_entry(&mut io, strtab, eh)?);
+        }
+
+        Ok(SectionContent::Symbols(r))
+    }
+
+    fn from_entry(io: &mut &[u8], strtab: Option<&Strtab>, eh: &Header) -> Result<Symbol, Error> {
+        let (name, info, other, shndx, value, size) = elf_dispatch_endianness!(eh => {
+            match eh.ident_class {
+                types::Class::Class32 => {
+                    let name = read_u32(io)?;
+                    let value = u64::from(read_u32(io)?);
+                    let size = u64::from(read_u32(io)?);
+                    let info = io.read_u8()?;
+                    let other = io.read_u8()?;
+                    let shndx = read_u16(io)?;
+                    (name, info, other, shndx, value, size)
+                }
+                types::Class::Class64 => {
+                    let name = read_u32(io)?;
+                    let info = io.read_u8()?;
+                    let other = io.read_u8()?;
+                    let shndx = read_u16(io)?;
+                    let value = read_u64(io)?;
+                    let size = read_u64(io)?;
+                    (name, info, other, shndx, value, size)
+                }
+            }
+        });
+
+        let stype = types::SymbolType::from(info & 0xf);
+        let bind = types::SymbolBind::from(info >> 4);
+        let vis = match types::SymbolVis::from_u8(other & 0x3) {
+            Some(v) => v,
+            None => return Err(Error::InvalidSymbolVis(other & 0x3)),
+        };
+
+        Ok(Symbol {
+            name: strtab.map(|s| s.get(name as usize)).unwrap_or_default(),
+            value,
+            size,
+            stype,
+            bind,
+            vis,
+            shndx: shndx.into(),
+        })
+    }
+}
+
+#[cfg(all(test, target_env = "gnu"))]
+mod test {
+    use super::{Symbol, SymbolSectionIndex};
+    use crate::elfkit::{types, Elf, Header};
+    use std::path::Path;
+
+    fn load_symbols(path: &Path, shtype: types::SectionType) -> Vec<Symbol> {
+        let mut f = std::fs::File::open(path).unwrap();
+        let mut elf = Elf::from_reader(&mut f).unwrap();
+        let shndx = elf
+            .sections
+            .iter()
+            .position(|s| s.header.shtype == shtype)
+            .unwrap();
+        elf.load(shndx, &mut f).unwrap();
+        elf.sections[shndx].content.as_symbols().unwrap().clone()
+    }
+
+    #[test]
+    fn test_symbols() {
+        let exe = std::env::current_exe().unwrap();
+        let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+        let libc = maps
+            .lines()
+            .filter_map(|l| l.split_whitespace().nth(5))
+            .find(|p| p.contains("/libc.so") || p.contains("/libc-"))
+            .unwrap();
+
+        let symtab = load_symbols(&exe, types::SectionType::SYMTAB);
+        assert!(symtab[0].name.is_empty());
+        assert!(!symtab[0].is_undefined());
+        let main = symtab.iter().find(|s| s.name == b"main").unwrap();
+        assert_eq!(main.stype, types::SymbolType::FUNC);
+        assert_eq!(main.bind, types::SymbolBind::GLOBAL);
+        assert!(matches!(main.shndx, SymbolSectionIndex::Section(_)));
+        assert!(main.value != 0);
+
+        let dynsym = load_symbols(&exe, types::SectionType::DYNSYM);
+        let memcpy = dynsym.iter().find(|s| s.name == b"memcpy").unwrap();
+        assert_eq!(memcpy.shndx, SymbolSectionIndex::Undefined);
+        assert!(memcpy.is_undefined());
+        assert!(!memcpy.is_exported());
+
+        let dynsym = load_symbols(Path::new(libc), types::SectionType::DYNSYM);
+        let malloc = dynsym
+            .iter()
+            .find(|s| s.name == b"malloc" && s.is_exported())
+            .unwrap();
+        assert_eq!(malloc.stype, types::SymbolType::FUNC);
+        assert_eq!(malloc.vis, types::SymbolVis::DEFAULT);
+        assert!(malloc.size != 0);
+    }
+
+    #[test]
+    fn test_unknown_type_and_bind() {
+        let eh = Header {
+            ident_class: types::Class::Class64,
+            ident_endianness: types::Endianness::LittleEndian,
+            ..Default::default()
+        };
+        // STT_LOPROC with STB_HIOS and STT_GNU_IFUNC with STB_GNU_UNIQUE
+        let mut entries = vec![0u8; 48];
+        entries[4] = 0xcd;
+        entries[24 + 4] = 0xaa;
+
+        let symbols = Symbol::from_reader(entries.as_slice(), None, &eh).unwrap();
+        let symbols = symbols.as_symbols().unwrap();
+        assert_eq!(symbols[0].stype, types::SymbolType::Other(13));
+        assert_eq!(symbols[0].bind, types::SymbolBind::Other(12));
+        assert_eq!(symbols[1].stype, types::SymbolType::GNU_IFUNC);
+        assert_eq!(symbols[1].bind, types::SymbolBind::STB_GNU_UNIQUE);
+    }
+}
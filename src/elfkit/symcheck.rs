@@ -0,0 +1,334 @@
+//! Check the dynamic symbols of an object against its dependency closure,
+//! like `ldd -r` does
+//!
+//! Every undefined, non-weak symbol of the executable and of the libraries it
+//! loads has to be exported by one of the objects of the closure in the version
+//! it was linked against, otherwise `ld.so` fails with "symbol lookup error",
+//! as soon as the symbol is bound.
+
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use hashbrown::{HashMap, HashSet};
+
+use crate::elfkit::hash::{GnuHash, SysvHash};
+use crate::elfkit::symbol::Symbol;
+use crate::elfkit::{types, Elf, SectionContent};
+
+/// The `.dynsym` section of an object, its hash tables and symbol versions
+#[derive(Debug, Clone, Default)]
+pub struct DynamicSymbols {
+    pub symbols: Vec<Symbol>,
+    pub gnu_hash: Option<GnuHash>,
+    pub sysv_hash: Option<SysvHash>,
+    /// the `.gnu.version` entry of every symbol, empty without symbol versioning
+    pub versym: Vec<u16>,
+    /// the names of the `DT_VERDEF` versions by version index
+    pub defined_versions: HashMap<u16, Vec<u8>>,
+    /// the names of the `DT_VERNEED` versions by version index
+    pub needed_versions: HashMap<u16, Vec<u8>>,
+}
+
+/// The version index bit of `.gnu.version`, which hides a symbol from unversioned lookups
+const VERSYM_HIDDEN: u16 = 0x8000;
+
+impl DynamicSymbols {
+    pub fn from_path(path: &Path) -> Result<DynamicSymbols, Box<dyn std::error::Error>> {
+        let mut f = File::open(path)?;
+        let mut elf = Elf::from_reader(&mut f).map_err(|e| format!("{:#?}", e))?;
+        let mut r = DynamicSymbols::default();
+
+        let dynsym = match elf
+            .sections
+            .iter()
+            .position(|s| s.header.shtype == types::SectionType::DYNSYM)
+        {
+            Some(i) => i,
+            None => return Ok(r),
+        };
+
+        for shndx in 0..elf.sections.len() {
+            let header = elf.sections[shndx].header.clone();
+            if (header.shtype == types::SectionType::GNU_HASH
+                || header.shtype == types::SectionType::HASH)
+                && header.link as usize == dynsym
+            {
+                elf.load(shndx, &mut f).map_err(|e| format!("{:#?}", e))?;
+                match std::mem::take(&mut elf.sections[shndx].content) {
+                    SectionContent::GnuHash(h) => r.gnu_hash = Some(h),
+                    SectionContent::Hash(h) => r.sysv_hash = Some(h),
+                    _ => {}
+                }
+            } else if header.shtype == types::SectionType::GNU_VERSYM {
+                let mut data = vec![0u8; header.size as usize];
+                f.seek(SeekFrom::Start(header.offset))?;
+                f.read_exact(&mut data)?;
+                r.versym = data
+                    .chunks_exact(2)
+                    .map(|c| match elf.header.ident_endianness {
+                        types::Endianness::LittleEndian => u16::from_le_bytes([c[0], c[1]]),
+                        types::Endianness::BigEndian => u16::from_be_bytes([c[0], c[1]]),
+                    })
+                    .collect();
+            } else if header.shtype == types::SectionType::GNU_VERDEF {
+                elf.load(shndx, &mut f).map_err(|e| format!("{:#?}", e))?;
+                if let Some(verdef) = elf.sections[shndx].content.as_verdef() {
+                    for v in verdef {
+                        if let Some(name) = v.name() {
+                            r.defined_versions.insert(v.ndx, name.to_vec());
+                        }
+                    }
+                }
+            } else if header.shtype == types::SectionType::GNU_VERNEED {
+                elf.load(shndx, &mut f).map_err(|e| format!("{:#?}", e))?;
+                if let Some(verneed) = elf.sections[shndx].content.as_verneed() {
+                    for aux in verneed.iter().flat_map(|v| v.versions.iter()) {
+                        r.needed_versions.insert(aux.other, aux.name.clone());
+                    }
+                }
+            }
+        }
+
+        elf.load(dynsym, &mut f).map_err(|e| format!("{:#?}", e))?;
+        if let SectionContent::Symbols(s) = std::mem::take(&mut elf.sections[dynsym].content) {
+            r.symbols = s;
+        }
+
+        Ok(r)
+    }
+
+    /// Check, if the object exports a symbol named `name` in the `version`,
+    /// like the symbol lookup of glibc's `ld.so` does.
+    ///
+    /// A versioned reference needs a definition of exactly that version. An
+    /// unversioned reference takes the base or oldest version of a symbol or
+    /// its only non-hidden version. Objects without symbol versioning satisfy
+    /// every reference by name.
+    pub fn defines(&self, name: &[u8], version: Option<&[u8]>) -> bool {
+        let found = if let Some(h) = &self.gnu_hash {
+            h.lookup(name, &self.symbols)
+        } else if let Some(h) = &self.sysv_hash {
+            h.lookup(name, &self.symbols)
+        } else {
+            (0..self.symbols.len())
+                .filter(|i| self.symbols[*i].name == name)
+                .collect()
+        };
+        let found = found
+            .into_iter()
+            .filter(|i| self.symbols[*i].is_exported())
+            .collect::<Vec<_>>();
+
+        if self.versym.is_empty() {
+            return !found.is_empty();
+        }
+        let versym = |i: usize| self.versym.get(i).copied().unwrap_or(1);
+
+        match version {
+            Some(version) => found.iter().any(|i| {
+                let ndx = versym(*i) & !VERSYM_HIDDEN;
+                match self.defined_versions.get(&ndx) {
+                    Some(name) => name == version,
+                    // a symbol without a version definition matches, if it is not hidden
+                    None => versym(*i) & VERSYM_HIDDEN == 0,
+                }
+            }),
+            None => {
+                found.iter().any(|i| versym(*i) & !VERSYM_HIDDEN < 3)
+                    || found
+                        .iter()
+                        .filter(|i| versym(**i) & VERSYM_HIDDEN == 0)
+                        .count()
+                        == 1
+            }
+        }
+    }
+
+    /// The undefined symbols, which have to be provided by another object,
+    /// with their `DT_VERNEED` version
+    pub fn required(&self) -> impl Iterator<Item = (&Symbol, Option<&[u8]>)> {
+        self.symbols
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.is_undefined() && s.bind != types::SymbolBind::WEAK)
+            .map(move |(i, s)| {
+                let version = self
+                    .versym
+                    .get(i)
+                    .and_then(|ndx| self.needed_versions.get(&(ndx & !VERSYM_HIDDEN)))
+                    .map(Vec::as_slice);
+                (s, version)
+            })
+    }
+}
+
+/// An undefined symbol, which no object of the closure exports
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnresolvedSymbol {
+    /// the object requiring the symbol
+    pub object: OsString,
+    pub name: Vec<u8>,
+    /// the required `DT_VERNEED` version
+    pub version: Option<Vec<u8>>,
+}
+
+/// The result of checking one executable
+#[derive(Debug, Clone)]
+pub struct SymbolReport {
+    pub path: OsString,
+    pub unresolved: Vec<UnresolvedSymbol>,
+}
+
+/// Check the objects of a dependency closure.
+///
+/// `closure` starts with the executable followed by the libraries in load order.
+/// Objects missing in `symbols` are ignored.
+pub fn check_closure(
+    closure: &[OsString],
+    symbols: &HashMap<OsString, DynamicSymbols>,
+) -> Vec<UnresolvedSymbol> {
+    let objects = closure
+        .iter()
+        .filter_map(|p| symbols.get(p))
+        .collect::<Vec<_>>();
+
+    let mut res = Vec::new();
+    for path in closure {
+        let dynsym = match symbols.get(path) {
+            Some(d) => d,
+            None => continue,
+        };
+        let mut seen = HashSet::new();
+        for (sym, version) in dynsym.required() {
+            if !seen.insert((&sym.name, version)) {
+                continue;
+            }
+            if !objects.iter().any(|o| o.defines(&sym.name, version)) {
+                res.push(UnresolvedSymbol {
+                    object: path.clone(),
+                    name: sym.name.clone(),
+                    version: version.map(<[u8]>::to_vec),
+                });
+            }
+        }
+    }
+    res
+}
+
+/// Collect the dependency closure of `path` in breadth first load order
+/// from the resolved `DT_NEEDED` libraries of every object.
+pub fn dependency_closure(
+    path: &OsString,
+    dependencies: &HashMap<OsString, Vec<OsString>>,
+) -> Vec<OsString> {
+    let mut closure = vec![path.clone()];
+    let mut seen = HashSet::new();
+    seen.insert(path.clone());
+    let mut i = 0;
+    while i < closure.len() {
+        if let Some(deps) = dependencies.get(&closure[i]) {
+            for dep in deps {
+                if seen.insert(dep.clone()) {
+                    closure.push(dep.clone());
+                }
+            }
+        }
+        i += 1;
+    }
+    closure
+}
+
+#[cfg(all(test, target_env = "gnu"))]
+mod test {
+    use super::{check_closure, DynamicSymbols, UnresolvedSymbol};
+    use crate::elfkit::symbol::{Symbol, SymbolSectionIndex};
+    use crate::elfkit::types;
+    use hashbrown::HashMap;
+    use std::ffi::OsString;
+    use std::path::Path;
+
+    #[test]
+    fn test_libc_symbols() {
+        let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+        let libc = maps
+            .lines()
+            .filter_map(|l| l.split_whitespace().nth(5))
+            .find(|p| p.contains("/libc.so") || p.contains("/libc-"))
+            .unwrap();
+
+        let exe = DynamicSymbols::from_path(&std::env::current_exe().unwrap()).unwrap();
+        let libc = DynamicSymbols::from_path(Path::new(libc)).unwrap();
+
+        assert!(libc.gnu_hash.is_some() || libc.sysv_hash.is_some());
+        assert!(libc.defines(b"malloc", None));
+        assert!(libc.defines(b"printf", None));
+        assert!(!libc.defines(b"__this_symbol_does_not_exist", None));
+
+        // the executable binds malloc to a version, which libc defines
+        let (malloc, version) = exe.required().find(|(s, _)| s.name == b"malloc").unwrap();
+        let version = version.unwrap();
+        assert!(version.starts_with(b"GLIBC_"));
+        assert!(libc.defines(&malloc.name, Some(version)));
+        assert!(!libc.defines(&malloc.name, Some(b"GLIBC_0.0")));
+    }
+
+    #[test]
+    fn test_check_closure_versions() {
+        let sym = |name: &str, shndx: SymbolSectionIndex| Symbol {
+            name: name.into(),
+            bind: types::SymbolBind::GLOBAL,
+            shndx,
+            ..Default::default()
+        };
+        let versions = |v: &[(u16, &str)]| {
+            v.iter()
+                .map(|(ndx, name)| (*ndx, name.as_bytes().to_vec()))
+                .collect::<HashMap<_, _>>()
+        };
+
+        let exe = DynamicSymbols {
+            symbols: vec![
+                Symbol::default(),
+                sym("foo", SymbolSectionIndex::Undefined),
+                sym("bar", SymbolSectionIndex::Undefined),
+                sym("baz", SymbolSectionIndex::Undefined),
+            ],
+            versym: vec![0, 2, 3, 1],
+            needed_versions: versions(&[(2, "LIB_2.34"), (3, "LIB_1.0")]),
+            ..Default::default()
+        };
+        // foo is only defined unversioned, bar in another version
+        let lib = DynamicSymbols {
+            symbols: vec![
+                Symbol::default(),
+                sym("foo", SymbolSectionIndex::Section(1)),
+                sym("bar", SymbolSectionIndex::Section(1)),
+                sym("baz", SymbolSectionIndex::Section(1)),
+            ],
+            versym: vec![0, 1, 3, 3],
+            defined_versions: versions(&[(1, "libfoo.so.1"), (2, "LIB_1.0"), (3, "LIB_2.0")]),
+            ..Default::default()
+        };
+
+        let closure = vec![OsString::from("exe"), OsString::from("libfoo.so.1")];
+        let mut symbols = HashMap::new();
+        symbols.insert(closure[0].clone(), exe);
+        symbols.insert(closure[1].clone(), lib);
+
+        let unresolved = |name: &str, version: &str| UnresolvedSymbol {
+            object: "exe".into(),
+            name: name.into(),
+            version: Some(version.into()),
+        };
+        assert_eq!(
+            check_closure(&closure, &symbols),
+            [unresolved("foo", "LIB_2.34"), unresolved("bar", "LIB_1.0")]
+        );
+
+        // without symbol versioning, the library satisfies every reference by name
+        symbols.get_mut(&closure[1]).unwrap().versym.clear();
+        assert!(check_closure(&closure, &symbols).is_empty());
+    }
+}
@@ -315,27 +315,46 @@ impl Default for ElfType {
 }
 
 #[allow(non_camel_case_types)]
-#[derive(Debug, Primitive, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum SymbolType {
     /// Symbol type is unspecified
-    NOTYPE = 0,
+    NOTYPE,
     /// Symbol is a data object
-    OBJECT = 1,
+    OBJECT,
     /// Symbol is a code object
-    FUNC = 2,
+    FUNC,
     /// Symbol associated with a section
-    SECTION = 3,
+    SECTION,
     /// Symbol's name is file name
-    FILE = 4,
+    FILE,
     /// Symbol is a common data object
-    COMMON = 5,
+    COMMON,
     /// Symbol is thread-local data object
-    TLS = 6,
+    TLS,
     /// Number of defined types
-    NUM = 7,
+    NUM,
     /// Symbol is indirect code object
-    GNU_IFUNC = 10,
+    GNU_IFUNC,
+    /// OS or processor specific type
+    Other(u8),
+}
+
+impl From<u8> for SymbolType {
+    fn from(stype: u8) -> Self {
+        match stype {
+            0 => SymbolType::NOTYPE,
+            1 => SymbolType::OBJECT,
+            2 => SymbolType::FUNC,
+            3 => SymbolType::SECTION,
+            4 => SymbolType::FILE,
+            5 => SymbolType::COMMON,
+            6 => SymbolType::TLS,
+            7 => SymbolType::NUM,
+            10 => SymbolType::GNU_IFUNC,
+            t => SymbolType::Other(t),
+        }
+    }
 }
 
 impl Default for SymbolType {
@@ -345,18 +364,32 @@ impl Default for SymbolType {
 }
 
 #[allow(non_camel_case_types)]
-#[derive(Debug, Primitive, PartialOrd, Eq, Ord, PartialEq, Clone)]
+#[derive(Debug, PartialOrd, Eq, Ord, PartialEq, Clone)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum SymbolBind {
     /// Local symbol
-    LOCAL = 0,
+    LOCAL,
     /// Global symbol
-    GLOBAL = 1,
+    GLOBAL,
     /// Weak symbol
-    WEAK = 2,
+    WEAK,
 
     /// obscure gnu thing. i hope this is the same as global
-    STB_GNU_UNIQUE = 10,
+    STB_GNU_UNIQUE,
+    /// OS or processor specific binding
+    Other(u8),
+}
+
+impl From<u8> for SymbolBind {
+    fn from(bind: u8) -> Self {
+        match bind {
+            0 => SymbolBind::LOCAL,
+            1 => SymbolBind::GLOBAL,
+            2 => SymbolBind::WEAK,
+            10 => SymbolBind::STB_GNU_UNIQUE,
+            b => SymbolBind::Other(b),
+        }
+    }
 }
 
 impl Default for SymbolBind {
@@ -745,6 +778,8 @@ pub enum Machine {
     RISCV = 243,
     /// Linux BPF -- in-kernel virtual machine
     BPF = 247,
+    /// Digital Alpha, as used by Linux
+    ALPHA = 0x9026,
 }
 
 impl Default for Machine {
@@ -1022,3 +1057,15 @@ bitflags! {
             const PIE  = 1 << 27;
     }
 }
+
+bitflags! {
+#[derive(Default)]
+    pub struct VersionFlags: u16 {
+        /// version definition of the file itself
+        const BASE = 1;
+        /// weak version identifier
+        const WEAK = 1 << 1;
+        /// reference exists for informational purposes
+        const INFO = 1 << 2;
+    }
+}
@@ -0,0 +1,234 @@
+use super::types;
+use super::{Error, Header, SectionContent, Strtab};
+use std::io::Read;
+
+/// An entry of the `.gnu.version_d` section (`Elf_Verdef`)
+#[derive(Debug, Clone, Default)]
+pub struct VersionDefinition {
+    pub flags: types::VersionFlags,
+    /// version index as used in `.gnu.version`
+    pub ndx: u16,
+    pub hash: u32,
+    /// the version name followed by the names of its predecessors
+    pub names: Vec<Vec<u8>>,
+}
+
+impl VersionDefinition {
+    /// The name of the defined version
+    pub fn name(&self) -> Option<&[u8]> {
+        self.names.first().map(Vec::as_slice)
+    }
+
+    pub fn from_reader<R>(
+        mut io: R,
+        linked: Option<&SectionContent>,
+        eh: &Header,
+    ) -> Result<SectionContent, Error>
+    where
+        R: Read,
+    {
+        let strtab = linked_strtab(linked, "reading version definitions")?;
+        let mut data = Vec::new();
+        io.read_to_end(&mut data)?;
+
+        let mut r = Vec::new();
+        let mut offset = 0usize;
+
+        loop {
+            let mut io = data.get(offset..).ok_or(Error::UnexpectedSectionContent)?;
+            let (flags, ndx, cnt, hash, aux, next) = elf_dispatch_endianness!(eh => {
+                let _version = read_u16(&mut io)?;
+                let flags = read_u16(&mut io)?;
+                let ndx = read_u16(&mut io)?;
+                let cnt = read_u16(&mut io)?;
+                let hash = read_u32(&mut io)?;
+                let aux = read_u32(&mut io)?;
+                let next = read_u32(&mut io)?;
+                (flags, ndx, cnt, hash, aux, next)
+            });
+
+            let mut names = Vec::with_capacity(cnt as usize);
+            let mut aux_offset = offset + aux as usize;
+            for _ in 0..cnt {
+                let mut io = data
+                    .get(aux_offset..)
+                    .ok_or(Error::UnexpectedSectionContent)?;
+                let (name, next) = elf_dispatch_endianness!(eh => {
+                    (read_u32(&mut io)?, read_u32(&mut io)?)
+                });
+                names.push(strtab_get(strtab, name));
+                if next == 0 {
+                    break;
+                }
+                aux_offset += next as usize;
+            }
+
+            r.push(VersionDefinition {
+                flags: types::VersionFlags::from_bits_truncate(flags),
+                ndx,
+                hash,
+                names,
+            });
+
+            if next == 0 {
+                break;
+            }
+            offset += next as usize;
+        }
+
+        Ok(SectionContent::VerDef(r))
+    }
+}
+
+/// A required version of a [`VersionNeed`] (`Elf_Vernaux`)
+#[derive(Debug, Clone, Default)]
+pub struct VersionNeedAux {
+    pub hash: u32,
+    pub flags: types::VersionFlags,
+    /// version index as used in `.gnu.version`
+    pub other: u16,
+    pub name: Vec<u8>,
+}
+
+/// An entry of the `.gnu.version_r` section (`Elf_Verneed`)
+#[derive(Debug, Clone, Default)]
+pub struct VersionNeed {
+    /// the `DT_NEEDED` name of the object defining the versions
+    pub file: Vec<u8>,
+    pub versions: Vec<VersionNeedAux>,
+}
+
+impl VersionNeed {
+    pub fn from_reader<R>(
+        mut io: R,
+        linked: Option<&SectionContent>,
+        eh: &Header,
+    ) -> Result<SectionContent, Error>
+    where
+        R: Read,
+    {
+        let strtab = linked_strtab(linked, "reading version needs")?;
+        let mut data = Vec::new();
+        io.read_to_end(&mut data)?;
+
+        let mut r = Vec::new();
+        let mut offset = 0usize;
+
+        loop {
+            let mut io = data.get(offset..).ok_or(Error::UnexpectedSectionContent)?;
+            let (cnt, file, aux, next) = elf_dispatch_endianness!(eh => {
+                let _version = read_u16(&mut io)?;
+                let cnt = read_u16(&mut io)?;
+                let file = read_u32(&mut io)?;
+                let aux = read_u32(&mut io)?;
+                let next = read_u32(&mut io)?;
+                (cnt, file, aux, next)
+            });
+
+            let mut versions = Vec::with_capacity(cnt as usize);
+            let mut aux_offset = offset + aux as usize;
+            for _ in 0..cnt {
+                let mut io = data
+                    .get(aux_offset..)
+                    .ok_or(Error::UnexpectedSectionContent)?;
+                let (hash, flags, other, name, next) = elf_dispatch_endianness!(eh => {
+                    let hash = read_u32(&mut io)?;
+                    let flags = read_u16(&mut io)?;
+                    let other = read_u16(&mut io)?;
+                    let name = read_u32(&mut io)?;
+                    let next = read_u32(&mut io)?;
+                    (hash, flags, other, name, next)
+                });
+                versions.push(VersionNeedAux {
+                    hash,
+                    flags: types::VersionFlags::from_bits_truncate(flags),
+                    other,
+                    name: strtab_get(strtab, name),
+                });
+                if next == 0 {
+                    break;
+                }
+                aux_offset += next as usize;
+            }
+
+            r.push(VersionNeed {
+                file: strtab_get(strtab, file),
+                versions,
+            });
+
+            if next == 0 {
+                break;
+            }
+            offset += next as usize;
+        }
+
+        Ok(SectionContent::VerNeed(r))
+    }
+}
+
+fn linked_strtab<'a>(
+    linked: Option<&'a SectionContent>,
+    during: &'static str,
+) -> Result<Option<&'a Strtab>, Error> {
+    match linked {
+        None => Ok(None),
+        Some(SectionContent::Strtab(s)) => Ok(Some(s)),
+        any => Err(Error::LinkedSectionIsNotStrtab {
+            during,
+            link: any.cloned(),
+        }),
+    }
+}
+
+fn strtab_get(strtab: Option<&Strtab>, i: u32) -> Vec<u8> {
+    strtab.map(|s| s.get(i as usize)).unwrap_or_default()
+}
+
+#[cfg(all(test, target_env = "gnu"))]
+mod test {
+    use crate::elfkit::{types, Elf, SectionContent};
+    use std::fs::File;
+    use std::path::Path;
+
+    fn load_sections(path: &Path, shtype: types::SectionType) -> Vec<SectionContent> {
+        let mut f = File::open(path).unwrap();
+        let mut elf = Elf::from_reader(&mut f).unwrap();
+        let mut res = Vec::new();
+        for shndx in 0..elf.sections.len() {
+            if elf.sections[shndx].header.shtype == shtype {
+                elf.load(shndx, &mut f).unwrap();
+                res.push(elf.sections[shndx].content.clone());
+            }
+        }
+        res
+    }
+
+    #[test]
+    fn test_libc_versions() {
+        let exe = std::env::current_exe().unwrap();
+        let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+        let libc = maps
+            .lines()
+            .filter_map(|l| l.split_whitespace().nth(5))
+            .find(|p| p.contains("/libc.so") || p.contains("/libc-"))
+            .unwrap();
+
+        let verneed = load_sections(&exe, types::SectionType::GNU_VERNEED);
+        let need = verneed
+            .iter()
+            .flat_map(|s| s.as_verneed().unwrap().iter())
+            .find(|n| n.file.starts_with(b"libc.so"))
+            .expect("test binary requires no libc versions");
+        assert!(need.versions.iter().all(|v| v.name.starts_with(b"GLIBC_")));
+
+        let verdef = load_sections(Path::new(libc), types::SectionType::GNU_VERDEF);
+        let defined = verdef
+            .iter()
+            .flat_map(|s| s.as_verdef().unwrap().iter())
+            .collect::<Vec<_>>();
+        assert!(defined[0].flags.contains(types::VersionFlags::BASE));
+        for v in need.versions.iter() {
+            assert!(defined.iter().any(|d| d.name() == Some(v.name.as_slice())));
+        }
+    }
+}
@@ -11,6 +11,7 @@ use itertools::{EitherOrBoth, Itertools};
 use libc::{fstat64, ftruncate64, lseek64, stat64};
 
 use crate::acl::acl_copy_fd;
+use crate::elfkit::strip::strip_debug;
 
 #[doc(hidden)]
 pub trait IsMinusOne {
@@ -90,6 +91,73 @@ pub fn canonicalize_dir(source: PathBuf) -> ChainResult<PathBuf, String> {
     Ok(source)
 }
 
+/// The path of the absolute `path` below the alternate root directory `root`
+pub fn in_root(root: &Path, path: &Path) -> PathBuf {
+    match path.strip_prefix("/") {
+        Ok(rel) if root != Path::new("/") => root.join(rel),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Canonicalize `path` as if `root` was the root directory.
+///
+/// Absolute symlinks and `..` never leave `root`.
+/// The returned path is relative to `root`, not to the host root.
+pub fn canonicalize_in(root: &Path, path: &Path) -> io::Result<PathBuf> {
+    use std::collections::VecDeque;
+    use std::path::Component;
+
+    if root == Path::new("/") {
+        return path.canonicalize();
+    }
+
+    let mut res = PathBuf::from("/");
+    let mut pending = path
+        .components()
+        .map(|c| c.as_os_str().to_os_string())
+        .collect::<VecDeque<_>>();
+    let mut links = 0;
+
+    while let Some(c) = pending.pop_front() {
+        match Path::new(&c).components().next() {
+            Some(Component::Normal(name)) => {
+                let candidate = res.join(name);
+                let meta = fs::symlink_metadata(in_root(root, &candidate))?;
+                if !meta.file_type().is_symlink() {
+                    res = candidate;
+                    continue;
+                }
+                // the limit of the Linux kernel
+                links += 1;
+                if links > 40 {
+                    return Err(io::Error::from_raw_os_error(libc::ELOOP));
+                }
+                let target = fs::read_link(in_root(root, &candidate))?;
+                if target.has_root() {
+                    res = PathBuf::from("/");
+                }
+                for t in target.components().rev() {
+                    pending.push_front(t.as_os_str().to_os_string());
+                }
+            }
+            Some(Component::ParentDir) => {
+                res.pop();
+            }
+            _ => {}
+        }
+    }
+    Ok(res)
+}
+
+/// [`canonicalize_dir`] inside the alternate root `root`
+pub fn canonicalize_dir_in(root: &Path, source: &Path) -> io::Result<PathBuf> {
+    let invalid = || io::Error::from(ErrorKind::InvalidInput);
+    let source_filename = source.file_name().ok_or_else(invalid)?;
+    let mut source = canonicalize_in(root, source.parent().ok_or_else(invalid)?)?;
+    source.push(source_filename);
+    Ok(source)
+}
+
 pub fn convert_abs_rel(source: &Path, target: &Path) -> ChainResult<PathBuf, String> {
     let mut target_rel = PathBuf::new();
     let mut rest = PathBuf::new();
@@ -133,6 +201,19 @@ pub fn ln_r(source: &Path, target: &Path) -> ChainResult<(), String> {
 pub fn clone_path(
     source: &Path,
     root_dir: &Path,
+) -> Result<(), Box<dyn std::error::Error + 'static + Send + Sync>> {
+    clone_path_in(Path::new("/"), source, root_dir, false)
+}
+
+/// Clone `source` of the alternate root `sysroot` with all its parent directories
+/// and symlinks to `root_dir`.
+///
+/// With `strip`, ELF objects are copied without their debug information.
+pub fn clone_path_in(
+    sysroot: &Path,
+    source: &Path,
+    root_dir: &Path,
+    strip: bool,
 ) -> Result<(), Box<dyn std::error::Error + 'static + Send + Sync>> {
     use os::unix::fs::DirBuilderExt;
     use std::fs::DirBuilder;
@@ -153,11 +234,12 @@ pub fn clone_path(
     }
 
     match source.parent() {
-        Some(s) => clone_path(s, root_dir)?,
+        Some(s) => clone_path_in(sysroot, s, root_dir, strip)?,
         _ => return Ok(()),
     }
 
-    let source_metadata = source
+    let host_source = in_root(sysroot, source);
+    let source_metadata = host_source
         .symlink_metadata()
         .context("Failed to get symlink metadata")?;
     let source_perms = source_metadata.permissions();
@@ -175,7 +257,7 @@ pub fn clone_path(
 
     let ret = if source_metadata.file_type().is_symlink() {
         let mut path =
-            fs::read_link(source).context(format!("Failed to read link of {:#?}", source))?;
+            fs::read_link(&host_source).context(format!("Failed to read link of {:#?}", source))?;
         if !path.has_root() {
             let mut sp = PathBuf::from(
                 source
@@ -185,7 +267,7 @@ pub fn clone_path(
             sp.push(path);
             path = sp;
         }
-        clone_path(&path, root_dir)?;
+        clone_path_in(sysroot, &path, root_dir, strip)?;
         eprintln!("clone_path symlink {:?} {:?}", path, target);
 
         let mut target_path = PathBuf::from(root_dir);
@@ -201,18 +283,22 @@ pub fn clone_path(
             "failed ln_r symlink {:?} {:?}",
             target_path, target
         ))
-    } else if source.is_dir() {
+    } else if host_source.is_dir() {
         eprintln!("clone_path mkdir {:?} {:?}", source, target);
         let mut builder = DirBuilder::new();
         builder.mode(source_perms.mode());
         builder
             .create(&target)
             .context(format!("clone_path mkdir {:?} {:?}", source, target))
-    } else if source.is_file() {
+    } else if host_source.is_file() {
         eprintln!("clone_path copy {:?} {:?}", source, target);
-        copy(source, &target)
-            .map(|_| ())
-            .context(format!("clone_path copy {:?} {:?}", source, target))
+        if strip {
+            copy_stripped(&host_source, &target)
+        } else {
+            copy(&host_source, &target)
+        }
+        .map(|_| ())
+        .context(format!("clone_path copy {:?} {:?}", source, target))
     } else {
         unimplemented!()
     };
@@ -225,6 +311,41 @@ pub fn clone_path(
     Ok(())
 }
 
+/// Copy `from` to `to` like [`copy`], but strip the debug information of ELF
+/// executables and shared libraries.
+///
+/// Other files, kernel modules and signed objects are copied unchanged.
+pub fn copy_stripped(from: &Path, to: &Path) -> ChainResult<u64, String> {
+    use io::{Read, Write};
+
+    let len = copy(from, to)?;
+
+    let mut magic = [0; 4];
+    let is_elf = fs::File::open(from)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .is_ok()
+        && magic == *b"\x7fELF";
+    if !is_elf {
+        return Ok(len);
+    }
+
+    let data = fs::read(from).context(format!("read {:?}", from))?;
+    let stripped = match strip_debug(&data) {
+        Ok(Some(s)) => s,
+        _ => return Ok(len),
+    };
+
+    // truncating keeps the mode, owner and ACLs set by `copy`
+    fs::OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(to)
+        .and_then(|mut f| f.write_all(&stripped))
+        .context(format!("write stripped {:?}", to))?;
+
+    Ok(stripped.len() as u64)
+}
+
 pub fn copy(from: &Path, to: &Path) -> ChainResult<u64, String> {
     use fs::{File, OpenOptions};
     use io::{Read, Write};
@@ -519,6 +640,43 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_canonicalize_in() {
+        use super::{canonicalize_dir_in, canonicalize_in};
+        use std::path::Path;
+
+        let tmp_dir = TempDir::new().unwrap();
+        let root = tmp_dir.path();
+        ::std::fs::create_dir_all(root.join("usr/lib64")).unwrap();
+        File::create(root.join("usr/lib64/libc.so.6")).unwrap();
+
+        // absolute symlinks and ".." are resolved inside the root
+        symlink("/usr/lib64", root.join("lib64")).unwrap();
+        symlink("../../lib64", root.join("usr/lib64/loop")).unwrap();
+        symlink("../../../../../usr", root.join("usr/lib64/up")).unwrap();
+
+        assert_eq!(
+            canonicalize_in(root, Path::new("/lib64")).unwrap(),
+            PathBuf::from("/usr/lib64")
+        );
+        assert_eq!(
+            canonicalize_in(root, Path::new("/lib64/loop/libc.so.6")).unwrap(),
+            PathBuf::from("/usr/lib64/libc.so.6")
+        );
+        assert_eq!(
+            canonicalize_in(root, Path::new("/lib64/up/lib64")).unwrap(),
+            PathBuf::from("/usr/lib64")
+        );
+        assert_eq!(
+            canonicalize_dir_in(root, Path::new("/lib64/libfoo.so")).unwrap(),
+            PathBuf::from("/usr/lib64/libfoo.so")
+        );
+        assert!(canonicalize_in(root, Path::new("/nonexistent")).is_err());
+
+        symlink("self", root.join("self")).unwrap();
+        assert!(canonicalize_in(root, Path::new("/self")).is_err());
+    }
+
     #[test]
     fn test_cp() {
         use super::copy;
@@ -0,0 +1,172 @@
+//! The dependency graph of the installed files
+//!
+//! The roots are the requested files and modules, the edges point from an
+//! object to the libraries, modules and firmware it pulls into the image.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::{OsStr, OsString};
+use std::fmt::Write;
+
+use serde::Serialize;
+use serde_json::json;
+
+/// Why an object pulls in another
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeKind {
+    /// `DT_NEEDED` library
+    Needed,
+    /// library of a `.note.dlopen` feature
+    Dlopen,
+    /// module dependency from `modules.dep`
+    Depends,
+    /// `pre:` or `post:` soft dependency of a module
+    Softdep,
+    /// `weakdep=` of a module
+    Weakdep,
+    /// `firmware=` of a module
+    Firmware,
+}
+
+impl EdgeKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            EdgeKind::Needed => "needed",
+            EdgeKind::Dlopen => "dlopen",
+            EdgeKind::Depends => "depends",
+            EdgeKind::Softdep => "softdep",
+            EdgeKind::Weakdep => "weakdep",
+            EdgeKind::Firmware => "firmware",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DependencyGraph {
+    /// the requested files and modules
+    pub roots: BTreeSet<OsString>,
+    pub edges: BTreeMap<OsString, BTreeSet<(OsString, EdgeKind)>>,
+}
+
+impl DependencyGraph {
+    pub fn add_root(&mut self, node: &OsStr) {
+        self.roots.insert(node.into());
+    }
+
+    pub fn add_edge(&mut self, from: &OsStr, to: &OsStr, kind: EdgeKind) {
+        self.edges
+            .entry(from.into())
+            .or_default()
+            .insert((to.into(), kind));
+    }
+
+    /// Add all roots and edges of `other`
+    pub fn merge(&mut self, other: DependencyGraph) {
+        self.roots.extend(other.roots);
+        for (from, to) in other.edges {
+            self.edges.entry(from).or_default().extend(to);
+        }
+    }
+
+    /// All roots and the nodes reachable by an edge
+    pub fn nodes(&self) -> BTreeSet<&OsString> {
+        let mut nodes = self.roots.iter().collect::<BTreeSet<_>>();
+        for (from, to) in self.edges.iter() {
+            nodes.insert(from);
+            nodes.extend(to.iter().map(|(t, _)| t));
+        }
+        nodes
+    }
+
+    /// Render the graph in the Graphviz DOT language, roots are drawn as boxes.
+    pub fn to_dot(&self) -> String {
+        let quote = |s: &OsStr| {
+            format!(
+                "\"{}\"",
+                s.to_string_lossy()
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+            )
+        };
+
+        let mut out = String::from("digraph dependencies {\n");
+        for root in self.roots.iter() {
+            let _ = writeln!(out, "    {} [shape=box];", quote(root));
+        }
+        for (from, to) in self.edges.iter() {
+            for (t, kind) in to.iter() {
+                let _ = writeln!(
+                    out,
+                    "    {} -> {} [label=\"{}\"];",
+                    quote(from),
+                    quote(t),
+                    kind.as_str()
+                );
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// The graph as JSON object with the `roots`, `nodes` and `edges` arrays
+    pub fn to_json(&self) -> serde_json::Value {
+        let s = |s: &OsStr| s.to_string_lossy().into_owned();
+        json!({
+            "roots": self.roots.iter().map(|r| s(r)).collect::<Vec<_>>(),
+            "nodes": self.nodes().into_iter().map(|n| s(n)).collect::<Vec<_>>(),
+            "edges": self
+                .edges
+                .iter()
+                .flat_map(|(from, to)| {
+                    to.iter().map(move |(t, kind)| {
+                        json!({ "from": s(from), "to": s(t), "kind": kind })
+                    })
+                })
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DependencyGraph, EdgeKind};
+    use std::ffi::OsStr;
+
+    #[test]
+    fn test_graph_export() {
+        let mut g = DependencyGraph::default();
+        g.add_root(OsStr::new("/usr/bin/prog"));
+        g.add_edge(
+            OsStr::new("/usr/bin/prog"),
+            OsStr::new("/lib64/libc.so.6"),
+            EdgeKind::Needed,
+        );
+        g.add_edge(
+            OsStr::new("/usr/bin/prog"),
+            OsStr::new("/lib64/lib\"q\".so"),
+            EdgeKind::Dlopen,
+        );
+        g.add_edge(
+            OsStr::new("/lib64/lib\"q\".so"),
+            OsStr::new("/lib64/libc.so.6"),
+            EdgeKind::Needed,
+        );
+
+        assert_eq!(
+            g.to_dot(),
+            "digraph dependencies {\n    \
+             \"/usr/bin/prog\" [shape=box];\n    \
+             \"/lib64/lib\\\"q\\\".so\" -> \"/lib64/libc.so.6\" [label=\"needed\"];\n    \
+             \"/usr/bin/prog\" -> \"/lib64/lib\\\"q\\\".so\" [label=\"dlopen\"];\n    \
+             \"/usr/bin/prog\" -> \"/lib64/libc.so.6\" [label=\"needed\"];\n\
+             }\n"
+        );
+
+        let json = g.to_json();
+        assert_eq!(json["roots"][0], "/usr/bin/prog");
+        assert_eq!(json["nodes"].as_array().unwrap().len(), 3);
+        assert_eq!(json["edges"].as_array().unwrap().len(), 3);
+        assert_eq!(json["edges"][0]["kind"], "needed");
+        assert_eq!(json["edges"][1]["from"], "/usr/bin/prog");
+    }
+}
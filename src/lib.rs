@@ -2,34 +2,45 @@
 
 use std::ffi::OsStr;
 use std::ffi::OsString;
-use std::io;
-use std::io::Write;
 use std::os::unix::prelude::*;
 use std::path::{Path, PathBuf};
 use std::sync::RwLock;
 
-use hashbrown::HashSet;
+use hashbrown::{HashMap, HashSet};
 use rayon::prelude::*;
 
-use slog::{debug, o, Level, Logger};
+use slog::{debug, error, o, warn, Level, Logger};
 use walkdir::WalkDir;
 
 use chainerror::prelude::v1::*;
 
 use regex::bytes::Regex;
 
+use crate::elfkit::explain::Trace;
 use crate::elfkit::ld_so_cache::LdsoCache;
-use crate::elfkit::ldd::Ldd;
-use crate::file::{canonicalize_dir, clone_path};
-pub use crate::modules::modalias_list;
+use crate::elfkit::ld_so_conf::read_ld_so_conf;
+use crate::elfkit::ldd::{Ldd, LddError, LddQueueItem};
+use crate::elfkit::note::DlopenPriority;
+use crate::elfkit::symcheck::{check_closure, dependency_closure, DynamicSymbols, SymbolReport};
+use crate::elfkit::Elf;
+use crate::file::{canonicalize_dir_in, clone_path_in, in_root};
+use crate::graph::{DependencyGraph, EdgeKind};
+pub use crate::modules::alias::ModuleAliases;
+use crate::modules::depmod::{installed_module_names, module_name, write_module_metadata};
+use crate::modules::modprobe::{module_name_normalize, ModprobeConfig};
+pub use crate::modules::net::{net_modules, NetModules, NetSelector};
+pub use crate::modules::storage::{storage_modules, StorageModules};
+pub use crate::modules::{modalias_list, DeviceFilter};
 use dynqueue::IntoDynQueue;
 
 mod acl;
 mod cstrviter;
-mod elfkit;
+pub mod elfkit;
 mod file;
+pub mod graph;
 mod modules;
 mod readstruct;
+pub mod size;
 
 pub type ResultSend<T> = std::result::Result<T, Box<dyn std::error::Error + 'static + Send>>;
 
@@ -44,9 +55,34 @@ pub struct RunContext {
     pub resolvelazy: bool,
     pub resolvedeps: bool,
     pub hostonly: bool,
+    pub check_symbols: bool,
+    /// install ELF executables and libraries without debug information
+    pub strip: bool,
+    /// do not install the soft and weak dependencies of modules
+    pub no_softdeps: bool,
+    /// write the modprobe.d configuration of the installed modules to the destination
+    pub modprobe_config: bool,
+    /// install the `.note.dlopen` dependencies up to this priority, none by default
+    pub dlopen_priority: Option<DlopenPriority>,
+    /// print how the libraries with these sonames or paths were chosen
+    pub explain: Vec<OsString>,
     pub loglevel: Level,
     pub destrootdir: PathBuf,
+    /// resolve and install all files from this root directory
+    pub sysroot: PathBuf,
+    /// the kernel module directory, libkmod reads it and the `modprobe.d`
+    /// configuration from the host and not from [`RunContext::sysroot`]
     pub kerneldir: Option<OsString>,
+    /// the sysfs of the system to search for devices
+    pub sysfs: PathBuf,
+    /// the procfs of the system to read the loaded modules, mounts and routes from
+    pub procfs: PathBuf,
+    /// the devices to resolve the modaliases of
+    pub device_filter: DeviceFilter,
+    /// install the modules for the block device stacks of these mount points or devices
+    pub storage: Vec<PathBuf>,
+    /// install the modules for these network interfaces
+    pub net: Vec<NetSelector>,
     pub logdir: Option<OsString>,
     pub logger: Logger,
     pub mod_filter_path: Option<Regex>,
@@ -56,6 +92,10 @@ pub struct RunContext {
     pub mod_filter_noname: Option<Regex>,
     pub firmwaredirs: Vec<OsString>,
     pub pathdirs: Vec<OsString>,
+    /// the dependencies of all files and modules installed so far
+    pub graph: DependencyGraph,
+    /// the resolution traces of the libraries to explain, printed by the caller
+    pub traces: Vec<Trace>,
 }
 
 impl Default for RunContext {
@@ -71,9 +111,21 @@ impl Default for RunContext {
             resolvelazy: false,
             resolvedeps: false,
             hostonly: false,
+            check_symbols: false,
+            strip: false,
+            no_softdeps: false,
+            modprobe_config: false,
+            dlopen_priority: None,
+            explain: vec![],
             loglevel: Level::Critical,
             destrootdir: Default::default(),
+            sysroot: PathBuf::from("/"),
             kerneldir: None,
+            sysfs: PathBuf::from("/sys"),
+            procfs: PathBuf::from("/proc"),
+            device_filter: DeviceFilter::default(),
+            storage: vec![],
+            net: vec![],
             logdir: None,
             logger: slog::Logger::root(slog::Discard, o!()),
             mod_filter_path: None,
@@ -83,63 +135,210 @@ impl Default for RunContext {
             mod_filter_noname: None,
             firmwaredirs: vec![],
             pathdirs: vec![],
+            graph: DependencyGraph::default(),
+            traces: vec![],
         }
     }
 }
 
-pub fn ldd(files: &[OsString], report_error: bool, dest_path: &Path) -> Vec<OsString> {
-    let sysroot = OsStr::new("/");
-    let cache = LdsoCache::read_ld_so_cache(sysroot).ok();
+/// The objects to install for a set of files and the errors resolving them
+#[derive(Debug, Default)]
+pub struct LddReport {
+    /// the files themselves and all the objects they need
+    pub files: Vec<OsString>,
+    /// the errors of all objects, a file, which could not be read, is not in `files`
+    pub errors: Vec<LddError>,
+    /// the resolution traces of the libraries to explain
+    pub traces: Vec<Trace>,
+    /// the files as roots and the libraries they need
+    pub graph: DependencyGraph,
+}
+
+/// Resolve the libraries of `files`, which are not yet installed in `dest_path`.
+pub fn ldd(files: &[OsString], dest_path: &Path) -> LddReport {
+    ldd_dependencies(
+        files,
+        Some(dest_path),
+        None,
+        Path::new("/"),
+        &[],
+        &Logger::root(slog::Discard, o!()),
+    )
+    .0
+}
+
+/// Resolve `files` and return all objects to install
+/// together with the resolved `DT_NEEDED` libraries of every object.
+///
+/// Without `dest_path` every object is resolved, even if it is already installed.
+/// The `.note.dlopen` features up to `dlopen_priority` are installed as well.
+///
+/// `files` and the returned paths are absolute paths inside `sysroot`.
+///
+/// The resolution of every dependency matching one of the sonames or paths
+/// in `explain` is traced.
+fn ldd_dependencies(
+    files: &[OsString],
+    dest_path: Option<&Path>,
+    dlopen_priority: Option<DlopenPriority>,
+    sysroot: &Path,
+    explain: &[OsString],
+    logger: &Logger,
+) -> (LddReport, HashMap<OsString, Vec<OsString>>) {
+    let cache = LdsoCache::read_ld_so_cache(sysroot.as_os_str()).ok();
 
     let standard_libdirs = vec![OsString::from("/lib64/dyninst"), OsString::from("/lib64")];
     let visited = RwLock::new(HashSet::<OsString>::new());
-    let ldd = Ldd::new(cache.as_ref(), &standard_libdirs, dest_path);
-    let mut _buf = Vec::<u8>::new();
+    let mut ldd = Ldd::new(cache.as_ref(), &standard_libdirs, dest_path);
+    if cache.is_none() {
+        ldd.ld_so_conf = read_ld_so_conf(sysroot);
+    }
+    ldd.dlopen_priority = dlopen_priority;
+    ldd.sysroot = sysroot.to_path_buf();
+    ldd.trace = !explain.is_empty();
+    ldd.logger = logger.clone();
+
+    let mut report = LddReport::default();
+    let mut queue = Vec::with_capacity(files.len());
+    for path in files {
+        match canonicalize_dir_in(sysroot, Path::new(path)) {
+            Ok(path) => {
+                let path = path.into_os_string();
+                report.graph.add_root(&path);
+                visited.write().unwrap().insert(path.clone());
+                queue.push(LddQueueItem {
+                    path,
+                    ..Default::default()
+                });
+            }
+            Err(error) => report.errors.push(LddError::Unreadable {
+                path: path.clone(),
+                error,
+            }),
+        }
+    }
+
+    let res = queue
+        .into_dyn_queue()
+        .into_par_iter()
+        .filter_map(|(handle, item)| {
+            let path = item.path.clone();
+            let installed = match dest_path {
+                Some(dest_path) => {
+                    let mut dest = OsString::from(dest_path.as_os_str());
+                    dest.push(path.as_os_str());
+                    PathBuf::from(dest).exists()
+                }
+                None => false,
+            };
+            if installed {
+                return None;
+            }
+            match ldd.recurse(handle, &item, &visited) {
+                Ok(()) => Some((Some(path), Vec::new())),
+                Err(errors) => {
+                    let unreadable = errors
+                        .iter()
+                        .any(|e| matches!(e, LddError::Unreadable { path: p, .. } if *p == path));
+                    Some((if unreadable { None } else { Some(path) }, errors))
+                }
+            }
+        })
+        .collect::<Vec<_>>();
 
-    //let lpaths = HashSet::new();
+    for (path, errors) in res {
+        report.files.extend(path);
+        report.errors.extend(errors);
+    }
 
-    let dest = OsString::from(dest_path.as_os_str());
+    for (kind, deps) in [
+        (
+            EdgeKind::Dlopen,
+            ldd.dlopen_dependencies.into_inner().unwrap(),
+        ),
+        (EdgeKind::Needed, ldd.dependencies.read().unwrap().clone()),
+    ] {
+        for (from, to) in deps.iter() {
+            for t in to {
+                report.graph.add_edge(from, t, kind);
+            }
+        }
+    }
 
-    let filequeue = files
-        .to_vec()
-        .drain(..)
-        .map(|path| {
-            canonicalize_dir(PathBuf::from(path))
-                .unwrap()
-                .as_os_str()
-                .to_os_string()
+    report.traces = ldd
+        .traces
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .filter(|t| explain.iter().any(|e| t.matches(e)))
+        .collect();
+    report
+        .traces
+        .sort_by(|a, b| (&a.requester, &a.dependency).cmp(&(&b.requester, &b.dependency)));
+
+    (report, ldd.dependencies.into_inner().unwrap())
+}
+
+/// Check, that every undefined, non-weak symbol of the executables in `files`
+/// and of their libraries is defined by an object of their dependency closure.
+///
+/// Returns a report for every executable, shared libraries and other files are skipped.
+/// `files` are absolute paths inside `sysroot`.
+pub fn check_symbols(files: &[OsString], sysroot: &Path) -> Vec<SymbolReport> {
+    let (report, dependencies) = ldd_dependencies(
+        files,
+        None,
+        None,
+        sysroot,
+        &[],
+        &Logger::root(slog::Discard, o!()),
+    );
+    symbol_reports(files, &report.files, &dependencies, sysroot)
+}
+
+/// Check the symbols of the executables in `files` against the objects `resolved`
+/// for them by [`ldd_dependencies`] with the resolved `DT_NEEDED` libraries `dependencies`.
+fn symbol_reports(
+    files: &[OsString],
+    resolved: &[OsString],
+    dependencies: &HashMap<OsString, Vec<OsString>>,
+    sysroot: &Path,
+) -> Vec<SymbolReport> {
+    let symbols = resolved
+        .par_iter()
+        .filter_map(|path| {
+            DynamicSymbols::from_path(&in_root(sysroot, Path::new(path)))
+                .ok()
+                .map(|s| (path.clone(), s))
         })
+        .collect::<HashMap<_, _>>();
+
+    files
+        .iter()
+        .filter_map(|path| canonicalize_dir_in(sysroot, Path::new(path)).ok())
+        .filter(|path| is_dynamic_executable(&in_root(sysroot, path)))
         .map(|path| {
-            visited.write().unwrap().insert(path.clone());
-            (path, HashSet::new())
+            let path = path.into_os_string();
+            let closure = dependency_closure(&path, dependencies);
+            SymbolReport {
+                unresolved: check_closure(&closure, &symbols),
+                path,
+            }
         })
-        .collect::<Vec<_>>()
-        .into_dyn_queue();
+        .collect()
+}
 
-    filequeue
-        .into_par_iter()
-        .filter_map(|(handle, (path, lpaths))| {
-            let mut dest = dest.clone();
-            dest.push(path.as_os_str());
-            let dest = PathBuf::from(dest);
-            if !dest.exists() {
-                ldd.recurse(handle, &path, &lpaths, &visited)
-                    .unwrap_or_else(|e| {
-                        if report_error {
-                            let stderr = io::stderr();
-                            let mut stderr = stderr.lock();
-                            let _ = stderr.write_all(path.as_bytes());
-                            let _ = stderr.write_all(b": ");
-                            let _ = stderr.write_all(e.to_string().as_bytes());
-                            let _ = stderr.write_all(b"\n");
-                        }
-                    });
-                Some(path)
-            } else {
-                None
-            }
+/// Check, if `path` is an ELF executable requesting a program interpreter
+fn is_dynamic_executable(path: &Path) -> bool {
+    std::fs::File::open(path)
+        .ok()
+        .and_then(|mut f| Elf::from_reader(&mut f).ok())
+        .map(|elf| {
+            elf.segments
+                .iter()
+                .any(|s| s.phtype == elfkit::types::SegmentType::INTERP)
         })
-        .collect::<Vec<_>>()
+        .unwrap_or(false)
 }
 
 pub fn install_files_ldd(
@@ -150,9 +349,60 @@ pub fn install_files_ldd(
     debug!(ctx.logger, "FirmwareDirs = {:#?}", ctx.firmwaredirs);
     debug!(ctx.logger, "KernelDir = {:#?}", ctx.kerneldir);
 
-    let res = ldd(&files, true, &ctx.destrootdir);
-    debug!(ctx.logger, "install {:#?}", res);
-    install_files(ctx, &res)
+    // the symbol check needs the whole closure, including the installed libraries
+    let dest_path = if ctx.check_symbols {
+        None
+    } else {
+        Some(ctx.destrootdir.as_path())
+    };
+    let (mut report, dependencies) = ldd_dependencies(
+        files,
+        dest_path,
+        ctx.dlopen_priority,
+        &ctx.sysroot,
+        &ctx.explain,
+        &ctx.logger,
+    );
+    for e in report.errors.iter() {
+        match e {
+            // scripts and other files are installed without dependencies
+            LddError::NotElf { .. } => debug!(ctx.logger, "{}", e),
+            LddError::MissingDlopenFeature { .. } => warn!(ctx.logger, "{}", e),
+            _ => error!(ctx.logger, "{}", e),
+        }
+    }
+    ctx.traces.append(&mut report.traces);
+    debug!(ctx.logger, "install {:#?}", report.files);
+    ctx.graph.merge(std::mem::take(&mut report.graph));
+    install_files(ctx, &report.files)?;
+
+    if ctx.check_symbols {
+        let mut failed = 0;
+        for symbols in symbol_reports(files, &report.files, &dependencies, &ctx.sysroot) {
+            if symbols.unresolved.is_empty() {
+                continue;
+            }
+            failed += 1;
+            for u in symbols.unresolved {
+                let version = u
+                    .version
+                    .map(|v| format!("@{}", String::from_utf8_lossy(&v)))
+                    .unwrap_or_default();
+                error!(
+                    ctx.logger,
+                    "{}: undefined symbol: {}{} ({})",
+                    symbols.path.to_string_lossy(),
+                    String::from_utf8_lossy(&u.name),
+                    version,
+                    u.object.to_string_lossy()
+                );
+            }
+        }
+        if failed > 0 {
+            return Err(format!("{} executables with undefined symbols", failed).into());
+        }
+    }
+    Ok(())
 }
 
 pub fn install_files(
@@ -160,7 +410,7 @@ pub fn install_files(
     files: &[OsString],
 ) -> Result<(), Box<dyn std::error::Error + 'static + Send + Sync>> {
     for i in files {
-        clone_path(&PathBuf::from(i), &ctx.destrootdir)?;
+        clone_path_in(&ctx.sysroot, &PathBuf::from(i), &ctx.destrootdir, ctx.strip)?;
     }
 
     Ok(())
@@ -172,10 +422,40 @@ pub fn install_modules(
     module_args: &[OsString],
 ) -> Result<(), Box<dyn std::error::Error + 'static + Send + Sync>> {
     let visited = RwLock::new(HashSet::<OsString>::new());
+    let graph = RwLock::new(DependencyGraph::default());
 
     let kmod_ctx = kmod::Context::new_with(ctx.kerneldir.as_deref(), None)
         .context("kmod::Context::new_with")?;
 
+    let config = ModprobeConfig::from_kmod(&kmod_ctx).context("reading the modprobe config")?;
+    // blacklisted modules are only installed, if they are requested by name
+    let explicit = module_args
+        .iter()
+        .filter(|arg| !arg.as_bytes().starts_with(b"="))
+        .filter_map(|arg| Path::new(arg).file_name())
+        .map(|name| {
+            let name = name.as_bytes();
+            let stem = name.iter().position(|c| *c == b'.').unwrap_or(name.len());
+            module_name_normalize(&name[..stem])
+        })
+        .collect::<HashSet<_>>();
+
+    let mut module_args = module_args.to_vec();
+    if !ctx.storage.is_empty() {
+        let mountinfo =
+            std::fs::read(ctx.procfs.join("self/mountinfo")).context("reading mountinfo")?;
+        for target in ctx.storage.iter() {
+            let storage = storage_modules(&ctx.sysfs, &mountinfo, target)?;
+            debug!(ctx.logger, "storage modules of {:?}: {:?}", target, storage);
+            module_args.extend(storage.modules());
+        }
+    }
+    if !ctx.net.is_empty() {
+        let net = net_modules(&ctx.sysfs, &ctx.procfs, &ctx.net)?;
+        debug!(ctx.logger, "network modules: {:?}", net);
+        module_args.extend(net.modules());
+    }
+
     let (module_iterators, errors): (Vec<_>, Vec<_>) = module_args
         .iter()
         .flat_map(|module| {
@@ -254,7 +534,15 @@ pub fn install_modules(
 
     let install_errors: Vec<_> = modules
         .into_iter()
-        .map(|m| install_module(ctx, &kmod_ctx, &m, &visited, true))
+        .filter(|m| {
+            let name = m.name().unwrap_or_default();
+            if config.is_blacklisted(name) && !explicit.contains(name.as_bytes()) {
+                debug!(ctx.logger, "skipping blacklisted module {:?}", name);
+                return false;
+            }
+            true
+        })
+        .map(|m| install_module(ctx, &kmod_ctx, &m, &visited, &graph, &config, true))
         .filter(ChainResult::is_err)
         .map(ChainResult::unwrap_err)
         .collect();
@@ -267,8 +555,39 @@ pub fn install_modules(
     }
 
     let files = visited.write().unwrap().drain().collect::<Vec<_>>();
+    ctx.graph.merge(graph.into_inner().unwrap());
+
+    // libkmod resolves the modules on the host, not inside the sysroot
+    for f in files.iter() {
+        clone_path_in(Path::new("/"), Path::new(f), &ctx.destrootdir, ctx.strip)?;
+    }
+
+    let names = files
+        .iter()
+        .filter_map(|f| module_name(f.as_bytes()))
+        .collect::<HashSet<_>>();
+    if ctx.modprobe_config {
+        // the modules of earlier runs into the same destination keep their configuration
+        let installed = installed_module_names(Path::new(&kmod_ctx.dirname()), &ctx.destrootdir);
+        config
+            .write(&installed, &ctx.destrootdir)
+            .context("writing the modprobe.d configuration")?;
+    }
 
-    install_files(ctx, &files)
+    let programs = config.install_programs(&names, &ctx.pathdirs, &ctx.sysroot);
+    if !programs.is_empty() {
+        debug!(
+            ctx.logger,
+            "programs of install and remove commands: {:?}", programs
+        );
+        install_files_ldd(ctx, &programs)?;
+    }
+
+    write_module_metadata(Path::new(&kmod_ctx.dirname()), &ctx.destrootdir).context(format!(
+        "writing the module metadata of {:?}",
+        kmod_ctx.dirname()
+    ))?;
+    Ok(())
 }
 
 derive_str_context!(InstallModuleError);
@@ -333,6 +652,8 @@ fn install_module(
     kmod_ctx: &kmod::Context,
     module: &kmod::Module,
     visited: &RwLock<HashSet<OsString>>,
+    graph: &RwLock<DependencyGraph>,
+    config: &ModprobeConfig,
     filter: bool,
 ) -> ChainResult<(), InstallModuleError> {
     debug!(
@@ -359,26 +680,77 @@ fn install_module(
         return Ok(());
     }
 
+    if filter {
+        graph.write().unwrap().add_root(path);
+    }
+
     if visited.write().unwrap().insert(path.into()) {
         for m in module.dependencies() {
-            install_module(ctx, kmod_ctx, &m, visited, false)?;
+            if let Some(p) = m.path() {
+                graph.write().unwrap().add_edge(path, p, EdgeKind::Depends);
+            }
+            install_module(ctx, kmod_ctx, &m, visited, graph, config, false)?;
+        }
+
+        for fw in module.firmware().unwrap_or_default() {
+            let fw = ctx
+                .firmwaredirs
+                .iter()
+                .map(|dir| Path::new(dir).join(&fw))
+                .find(|p| in_root(&ctx.sysroot, p).exists())
+                .map(PathBuf::into_os_string)
+                .unwrap_or(fw);
+            graph
+                .write()
+                .unwrap()
+                .add_edge(path, &fw, EdgeKind::Firmware);
         }
 
-        if let Ok((pre, _post)) = module.soft_dependencies() {
-            for pre_mod in pre {
-                let name = pre_mod
-                    .name()
-                    .ok_or("pre_mod_error")
-                    .context(InstallModuleError(format!(
-                        "Failed to get name for {:?}",
-                        pre_mod
-                    )))?;
+        if !ctx.no_softdeps {
+            let mut soft = Vec::new();
+            if let Ok((pre, post)) = module.soft_dependencies() {
+                for soft_mod in pre.chain(post) {
+                    let name =
+                        soft_mod
+                            .name()
+                            .ok_or("soft_mod_error")
+                            .context(InstallModuleError(format!(
+                                "Failed to get name for {:?}",
+                                soft_mod
+                            )))?;
+                    soft.push((OsString::from(name), EdgeKind::Softdep));
+                }
+            }
+            match module.weak_dependencies() {
+                Ok(Some(weak)) => {
+                    for weak_mod in weak {
+                        if let Some(name) = weak_mod.name() {
+                            soft.push((OsString::from(name), EdgeKind::Weakdep));
+                        }
+                    }
+                }
+                // without libkmod 33, only the `weakdep=` of the module is known
+                _ => {
+                    for name in module.weakdeps().unwrap_or_default() {
+                        soft.push((OsString::from(name), EdgeKind::Weakdep));
+                    }
+                }
+            }
+
+            for (name, kind) in soft {
+                if config.is_blacklisted(&name) {
+                    debug!(ctx.logger, "skipping blacklisted {:?} {:?}", kind, name);
+                    continue;
+                }
                 let it = kmod_ctx
-                    .module_new_from_lookup(&OsString::from(&name))
+                    .module_new_from_lookup(&name)
                     .context(InstallModuleError(format!("Failed lookup for {:?}", name)))?;
                 for m in it {
-                    debug!(ctx.logger, "pre <{:?}>", m.path());
-                    install_module(ctx, kmod_ctx, &m, visited, false)?;
+                    debug!(ctx.logger, "{:?} <{:?}>", kind, m.path());
+                    if let Some(p) = m.path() {
+                        graph.write().unwrap().add_edge(path, p, kind);
+                    }
+                    install_module(ctx, kmod_ctx, &m, visited, graph, config, false)?;
                 }
             }
         }
@@ -391,6 +763,7 @@ fn install_module(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::elfkit::dst::DstError;
     use std::ffi::OsString;
 
     use slog::*;
@@ -434,7 +807,7 @@ mod tests {
             .unwrap()
             .map(|e| OsString::from(e.unwrap().path().as_os_str()))
             .collect::<Vec<_>>();
-        let mut res = ldd(&files, false, &tmpdir);
+        let mut res = ldd(&files, &tmpdir).files;
         eprintln!("no. files = {}", res.len());
         let hs: HashSet<OsString> = res.iter().cloned().collect();
         eprintln!("no. unique files = {}", hs.len());
@@ -442,4 +815,143 @@ mod tests {
         res.sort();
         eprintln!("files = {:#?}", res);
     }
+
+    #[test]
+    fn test_ldd_errors() {
+        let tmpdir = TempDir::new().unwrap();
+        let script = tmpdir.path().join("script.sh");
+        std::fs::write(&script, "#!/bin/sh\n").unwrap();
+        let missing = tmpdir.path().join("missing");
+        let dest = tmpdir.path().join("dest");
+
+        let report = ldd(
+            &[script.clone().into_os_string(), missing.into_os_string()],
+            &dest,
+        );
+
+        // a script is still installed, a missing file is not
+        assert_eq!(report.files, vec![script.clone().into_os_string()]);
+        assert!(report.graph.roots.contains(script.as_os_str()));
+        assert!(report.graph.edges.is_empty());
+        assert_eq!(report.errors.len(), 2);
+        assert!(report
+            .errors
+            .iter()
+            .any(|e| matches!(e, LddError::NotElf { .. })));
+        assert!(report
+            .errors
+            .iter()
+            .any(|e| matches!(e, LddError::Unreadable { .. })));
+    }
+
+    #[test]
+    fn test_ldd_refused_origin() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::process::Command;
+
+        let tmpdir = TempDir::new().unwrap();
+        let source = tmpdir.path().join("main.c");
+        std::fs::write(&source, "int main(void) { return 0; }\n").unwrap();
+        let exe = tmpdir.path().join("setuid");
+        let status = Command::new("cc")
+            .arg("-o")
+            .arg(&exe)
+            .arg(&source)
+            .arg("-Wl,-rpath,$ORIGIN/../lib:/opt/$ORIGIN")
+            .arg("-Wl,--disable-new-dtags")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o4755)).unwrap();
+
+        let report = ldd(&[exe.clone().into_os_string()], &tmpdir.path().join("dest"));
+
+        // $ORIGIN of a setuid executable is refused in the middle of an element
+        // and outside of the trusted directories
+        let refused = report
+            .errors
+            .iter()
+            .filter_map(|e| match e {
+                LddError::RefusedToken { requester, error } => {
+                    assert_eq!(requester, exe.as_os_str());
+                    Some(error.clone())
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            refused,
+            [
+                DstError::UntrustedOrigin("$ORIGIN/../lib".into()),
+                DstError::InsecureOrigin("/opt/$ORIGIN".into()),
+            ]
+        );
+        assert!(report.files.contains(&exe.into_os_string()));
+    }
+
+    #[test]
+    fn test_ldd_refused_origin_musl() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::process::Command;
+
+        let tmpdir = TempDir::new().unwrap();
+        let lib = tmpdir.path().join("lib");
+        std::fs::create_dir(&lib).unwrap();
+        let source = tmpdir.path().join("foo.c");
+        std::fs::write(&source, "int foo(void) { return 0; }\n").unwrap();
+        let status = Command::new("cc")
+            .args(["-shared", "-fPIC", "-nostdlib", "-o"])
+            .arg(lib.join("libfoo.so"))
+            .arg(&source)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let source = tmpdir.path().join("main.c");
+        std::fs::write(&source, "int foo(void);\nvoid _start(void) { foo(); }\n").unwrap();
+        let bin = tmpdir.path().join("bin");
+        std::fs::create_dir(&bin).unwrap();
+        let exe = bin.join("setuid");
+        let status = Command::new("cc")
+            .args(["-pie", "-nostdlib", "-o"])
+            .arg(&exe)
+            .arg(&source)
+            .arg(format!("-L{}", lib.display()))
+            .arg("-lfoo")
+            .arg(format!(
+                "-Wl,--dynamic-linker=/lib/ld-musl-{}.so.1",
+                std::env::consts::ARCH
+            ))
+            .arg("-Wl,-rpath,$ORIGIN/../lib")
+            .arg("-Wl,--disable-new-dtags")
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // musl expands $ORIGIN of an ordinary executable
+        let report = ldd(&[exe.clone().into_os_string()], &tmpdir.path().join("dest"));
+        assert!(report
+            .files
+            .contains(&lib.join("libfoo.so").into_os_string()));
+
+        // and drops the whole search path of a setuid executable
+        std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o4755)).unwrap();
+        let report = ldd(&[exe.clone().into_os_string()], &tmpdir.path().join("dest"));
+        let refused = report
+            .errors
+            .iter()
+            .filter_map(|e| match e {
+                LddError::RefusedToken { error, .. } => Some(error.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(refused, [DstError::InsecureOrigin("$ORIGIN/../lib".into())]);
+        assert!(report.errors.iter().any(|e| matches!(
+            e,
+            LddError::MissingDependency { dependency, .. } if dependency == "libfoo.so"
+        )));
+        assert!(!report
+            .files
+            .contains(&lib.join("libfoo.so").into_os_string()));
+    }
 }
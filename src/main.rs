@@ -11,7 +11,8 @@ use regex::bytes::Regex;
 use slog::*;
 use slog_async::OverflowStrategy;
 
-use dracut_install::{install_files_ldd, install_modules, modalias_list, RunContext};
+use dracut_install::size::{parse_size, HumanSize, SizeReport};
+use dracut_install::{install_files_ldd, install_modules, modalias_list, DeviceFilter, RunContext};
 
 //use itertools::Itertools;
 
@@ -75,6 +76,79 @@ fn main() {
                 .takes_value(false)
                 .required(false),
         )
+        .arg(
+            Arg::with_name("strip")
+                .long("strip")
+                .help("Strip debug sections and symbol tables from installed executables and libraries")
+                .takes_value(false)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("check-symbols")
+                .long("check-symbols")
+                .help(
+                    "Fail, if an installed executable needs a symbol none of its libraries defines",
+                )
+                .takes_value(false)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("dlopen")
+                .long("dlopen")
+                .value_name("PRIORITY")
+                .help("Install the dlopen() dependencies of ELF files up to <PRIORITY>")
+                .possible_values(&["none", "required", "recommended", "suggested"])
+                .default_value("none")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("explain")
+                .long("explain")
+                .value_name("SONAME|PATH")
+                .help("Print why and from where the library <SONAME|PATH> was chosen")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("graph")
+                .long("graph")
+                .value_name("FILE")
+                .help("Write the dependency graph of the installed files to FILE")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("graph-format")
+                .long("graph-format")
+                .value_name("FORMAT")
+                .help("Format of the dependency graph")
+                .possible_values(&["dot", "json"])
+                .default_value("dot")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("size-report")
+                .long("size-report")
+                .help("Print the installed bytes per argument and the largest files")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("size-budget")
+                .long("size-budget")
+                .value_name("SIZE")
+                .help("Fail, if the installed files exceed SIZE bytes (suffixes K, M, G)")
+                .takes_value(true)
+                .validator(|s| {
+                    parse_size(&s)
+                        .map(|_| ())
+                        .ok_or_else(|| format!("'{}' is not a size", s))
+                })
+                .required(false),
+        )
         .arg(
             Arg::with_name("resolvelazy")
                 .short("R")
@@ -130,6 +204,44 @@ fn main() {
                 .takes_value(false)
                 .required(false),
         )
+        .arg(
+            Arg::with_name("sysfs")
+                .long("sysfs")
+                .value_name("DIR")
+                .help("Search the devices for --modalias in the sysfs mounted at <DIR>")
+                .default_value("/sys")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("procfs")
+                .long("procfs")
+                .value_name("DIR")
+                .help("Read the loaded modules for --modalias, the mounts for --storage and the routes for --net from the procfs mounted at <DIR>")
+                .default_value("/proc")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("subsystem")
+                .long("subsystem")
+                .value_name("NAME")
+                .help("Only resolve the modaliases of devices of the subsystem or bus <NAME>")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("device")
+                .long("device")
+                .value_name("DEVPATH")
+                .help("Only resolve the modaliases of the devices below <DEVPATH>, like /devices/pci0000:00")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false),
+        )
         .arg(
             Arg::with_name("silent")
                 .long("silent")
@@ -146,6 +258,14 @@ fn main() {
                 .takes_value(true)
                 .required(false),
         )
+        .arg(
+            Arg::with_name("sysroot")
+                .long("sysroot")
+                .value_name("DIR")
+                .help("Resolve and install all SOURCE files and their libraries from <DIR> instead of /, kernel modules are always installed from the host")
+                .takes_value(true)
+                .required(false),
+        )
         .arg(
             Arg::with_name("logdir")
                 .short("L")
@@ -200,6 +320,26 @@ fn main() {
                 .takes_value(true)
                 .required(false),
         )
+        .arg(
+            Arg::with_name("storage")
+                .long("storage")
+                .value_name("MOUNTPOINT|DEVICE")
+                .help("Install the kernel modules for the file system and block device stack of <MOUNTPOINT|DEVICE>")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("net")
+                .long("net")
+                .value_name("IFACE|MAC|default")
+                .help("Install the kernel modules for the network interface <IFACE>, the interface with the address <MAC> or of the default route")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false),
+        )
         .arg(
             Arg::with_name("kerneldir")
                 .long("kerneldir")
@@ -208,6 +348,20 @@ fn main() {
                 .takes_value(true)
                 .required(false),
         )
+        .arg(
+            Arg::with_name("no-softdeps")
+                .long("no-softdeps")
+                .help("Do not install soft and weak dependencies of kernel modules")
+                .takes_value(false)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("modprobe-config")
+                .long("modprobe-config")
+                .help("Write the modprobe.d options, aliases, softdeps and blacklists of the installed kernel modules to DESTROOTDIR/etc/modprobe.d/")
+                .takes_value(false)
+                .required(false),
+        )
         .arg(
             Arg::with_name("firmwaredirs")
                 .long("firmwaredirs")
@@ -243,11 +397,25 @@ fn main() {
         createdir: matches.is_present("createdir"),
         optional: matches.is_present("optional"),
         silent: matches.is_present("silent"),
-        module: matches.is_present("module"),
+        module: matches.is_present("module")
+            || matches.is_present("storage")
+            || matches.is_present("net"),
         modalias: matches.is_present("modalias"),
         resolvelazy: matches.is_present("resolvelazy"),
         resolvedeps: matches.is_present("resolvedeps"),
         hostonly: matches.is_present("hostonly"),
+        check_symbols: matches.is_present("check-symbols"),
+        strip: matches.is_present("strip"),
+        no_softdeps: matches.is_present("no-softdeps"),
+        modprobe_config: matches.is_present("modprobe-config"),
+        explain: matches
+            .values_of_os("explain")
+            .map(|v| v.map(OsString::from).collect())
+            .unwrap_or_default(),
+        dlopen_priority: match matches.value_of("dlopen") {
+            None | Some("none") => None,
+            Some(p) => Some(p.parse().unwrap()),
+        },
         loglevel: if matches.is_present("debug") {
             Level::Debug
         } else {
@@ -259,7 +427,31 @@ fn main() {
                 _ => Level::Trace,
             }
         },
+        sysroot: matches
+            .value_of_os("sysroot")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("/")),
         kerneldir: matches.value_of_os("kerneldir").map(OsString::from),
+        sysfs: PathBuf::from(matches.value_of_os("sysfs").unwrap()),
+        procfs: PathBuf::from(matches.value_of_os("procfs").unwrap()),
+        device_filter: DeviceFilter {
+            subsystems: matches
+                .values_of_os("subsystem")
+                .map(|v| v.map(OsString::from).collect())
+                .unwrap_or_default(),
+            path_prefixes: matches
+                .values_of_os("device")
+                .map(|v| v.map(PathBuf::from).collect())
+                .unwrap_or_default(),
+        },
+        storage: matches
+            .values_of_os("storage")
+            .map(|v| v.map(PathBuf::from).collect())
+            .unwrap_or_default(),
+        net: matches
+            .values_of("net")
+            .map(|v| v.map(|s| s.parse().unwrap()).collect())
+            .unwrap_or_default(),
         logdir: matches.value_of_os("logdir").map(OsString::from),
         mod_filter_path: matches.value_of_os("mod-filter-path").map(|s| {
             let s = s.to_string_lossy();
@@ -321,6 +513,8 @@ fn main() {
             .next()
             .unwrap_or_default(),
         logger: slog::Logger::root(slog::Discard, o!()),
+        graph: Default::default(),
+        traces: vec![],
     };
 
     let files = match matches.values_of_os("arg") {
@@ -328,7 +522,18 @@ fn main() {
         None => Vec::<OsString>::new(),
     };
 
-    if let Err(e) = do_main(&mut ctx, &files) {
+    let reports = Reports {
+        graph: matches.value_of_os("graph").map(|file| {
+            (
+                PathBuf::from(file),
+                matches.value_of("graph-format") == Some("json"),
+            )
+        }),
+        size_report: matches.is_present("size-report"),
+        size_budget: matches.value_of("size-budget").and_then(parse_size),
+    };
+
+    if let Err(e) = do_main(&mut ctx, &files, &reports) {
         match ctx.loglevel {
             Level::Debug | Level::Trace => {
                 error!(ctx.logger, "{:?}", e);
@@ -342,7 +547,15 @@ fn main() {
     }
 }
 
-fn do_main(ctx: &mut RunContext, args: &[OsString]) -> Result<()> {
+/// What to report about the installed files
+struct Reports {
+    /// the file to write the dependency graph to, in JSON instead of DOT
+    graph: Option<(PathBuf, bool)>,
+    size_report: bool,
+    size_budget: Option<u64>,
+}
+
+fn do_main(ctx: &mut RunContext, args: &[OsString], reports: &Reports) -> Result<()> {
     // Setup logging
     if let Some(dir) = &ctx.logdir {
         let logfile_path = PathBuf::from(dir).join(format!("{}.log", unsafe { libc::getpid() }));
@@ -372,32 +585,84 @@ fn do_main(ctx: &mut RunContext, args: &[OsString]) -> Result<()> {
         ctx.logger = Logger::root(drain, o!());
     }
 
-    // Get running kernel version, if kerneldir is unset
-    if ctx.kerneldir.is_none() {
+    // Get running kernel version
+    let running_kerneldir = {
         let mut utsname: libc::utsname = unsafe { std::mem::MaybeUninit::uninit().assume_init() };
         let ret = unsafe { libc::uname(&mut utsname) };
         if ret == -1 {
             return Err(Box::from(std::io::Error::last_os_error()));
         }
-        ctx.kerneldir = Some({
-            let mut s = OsString::from("/lib/modules/");
-            s.push(OsStr::from_bytes(
-                unsafe { CStr::from_ptr(&utsname.release as *const libc::c_char) }.to_bytes(),
-            ));
-            s
-        });
+        let mut s = OsString::from("/lib/modules/");
+        s.push(OsStr::from_bytes(
+            unsafe { CStr::from_ptr(&utsname.release as *const libc::c_char) }.to_bytes(),
+        ));
+        PathBuf::from(s)
+    };
+    if ctx.kerneldir.is_none() {
+        ctx.kerneldir = Some(running_kerneldir.clone().into_os_string());
     }
 
     if ctx.modalias {
-        for m in modalias_list()? {
+        let kerneldir = PathBuf::from(ctx.kerneldir.as_ref().unwrap());
+        let report = modalias_list(
+            &kerneldir,
+            Some(&running_kerneldir),
+            &ctx.sysfs,
+            &ctx.procfs,
+            &ctx.device_filter,
+        )?;
+        for m in report.modules {
             println!("{:?}", m);
         }
+        for m in report.missing_modules {
+            warn!(
+                ctx.logger,
+                "Loaded module {:?} has no counterpart in {:?}", m, kerneldir
+            );
+        }
+        for a in report.unmatched_aliases {
+            warn!(
+                ctx.logger,
+                "No module in {:?} for device {:?}", kerneldir, a
+            );
+        }
         return Ok(());
     }
 
     if ctx.module {
-        install_modules(ctx, args)
+        install_modules(ctx, args)?;
     } else {
-        install_files_ldd(ctx, args)
+        install_files_ldd(ctx, args)?;
+    }
+
+    for t in ctx.traces.iter() {
+        print!("{}", t);
+    }
+
+    if let Some((file, json)) = &reports.graph {
+        let out = if *json {
+            serde_json::to_string_pretty(&ctx.graph.to_json())?
+        } else {
+            ctx.graph.to_dot()
+        };
+        std::fs::write(file, out)?;
+    }
+
+    if reports.size_report || reports.size_budget.is_some() {
+        let size = SizeReport::new(&ctx.graph, &ctx.destrootdir, 10);
+        if reports.size_report {
+            print!("{}", size);
+        }
+        if let Some(budget) = reports.size_budget {
+            if size.total > budget {
+                return Err(format!(
+                    "installed files of {} exceed the size budget of {}",
+                    HumanSize(size.total),
+                    HumanSize(budget)
+                )
+                .into());
+            }
+        }
     }
+    Ok(())
 }
@@ -0,0 +1,266 @@
+//! Matching of device modaliases against the `modules.alias` of a kernel directory
+//!
+//! This does the alias part of `kmod_module_new_from_lookup` without libkmod, for any
+//! kernel version. Patterns are matched like `fnmatch(3)` without flags.
+
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::os::unix::prelude::*;
+use std::path::Path;
+
+use super::index::Index;
+
+/// The aliases of the modules of one kernel
+#[derive(Debug, Clone, Default)]
+pub struct ModuleAliases {
+    /// normalized patterns with their module, in the order of the index
+    aliases: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl ModuleAliases {
+    /// Read `modules.alias` of `kernel_dir`, or `modules.alias.bin`, if there is no
+    /// text file.
+    pub fn from_kernel_dir(kernel_dir: &Path) -> io::Result<ModuleAliases> {
+        match fs::read(kernel_dir.join("modules.alias")) {
+            Ok(content) => Ok(ModuleAliases::parse(&content)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                ModuleAliases::from_index(&fs::read(kernel_dir.join("modules.alias.bin"))?)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Parse the `alias <pattern> <module>` lines of `modules.alias`
+    pub fn parse(content: &[u8]) -> ModuleAliases {
+        let aliases = content
+            .split(|c| *c == b'\n')
+            .filter_map(|line| {
+                let mut words = line
+                    .split(|c| c.is_ascii_whitespace())
+                    .filter(|w| !w.is_empty());
+                match (words.next(), words.next(), words.next()) {
+                    (Some(b"alias"), Some(pattern), Some(module)) => {
+                        Some((alias_normalize(pattern), module.to_vec()))
+                    }
+                    _ => None,
+                }
+            })
+            .collect();
+        ModuleAliases { aliases }
+    }
+
+    /// Read the binary `modules.alias.bin`
+    pub fn from_index(data: &[u8]) -> io::Result<ModuleAliases> {
+        let mut aliases = Index::from_bytes(data)?
+            .entries()
+            .into_iter()
+            .flat_map(|(pattern, values)| {
+                values
+                    .into_iter()
+                    .map(move |(priority, module)| (priority, pattern.clone(), module))
+            })
+            .collect::<Vec<_>>();
+        aliases.sort_by_key(|(priority, _, _)| *priority);
+        Ok(ModuleAliases {
+            aliases: aliases.into_iter().map(|(_, p, m)| (p, m)).collect(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+    }
+
+    /// The names of the modules with an alias matching `modalias`, without duplicates
+    pub fn lookup(&self, modalias: &[u8]) -> Vec<OsString> {
+        let modalias = alias_normalize(modalias);
+        let mut modules = Vec::<OsString>::new();
+        for (pattern, module) in self.aliases.iter() {
+            let module = OsString::from_vec(module.clone());
+            if fnmatch(pattern, &modalias) && !modules.contains(&module) {
+                modules.push(module);
+            }
+        }
+        modules
+    }
+}
+
+/// Replace `-` by `_` outside of bracket expressions, like libkmod does for aliases.
+pub fn alias_normalize(alias: &[u8]) -> Vec<u8> {
+    let mut in_bracket = false;
+    alias
+        .iter()
+        .map(|c| match *c {
+            b'[' => {
+                in_bracket = true;
+                b'['
+            }
+            b']' => {
+                in_bracket = false;
+                b']'
+            }
+            b'-' if !in_bracket => b'_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Match `name` against the shell wildcard `pattern` with `*`, `?`, `[…]` and `\`.
+pub fn fnmatch(pattern: &[u8], name: &[u8]) -> bool {
+    // the position after the last `*` and the name position it matched up to
+    let mut backtrack: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+
+    while n < name.len() {
+        let step = match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p + 1, n));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => Some(p + 1),
+            Some(b'[') => match bracket(&pattern[p..], name[n]) {
+                Some((true, len)) => Some(p + len),
+                Some((false, _)) => None,
+                // an unclosed bracket matches itself
+                None if name[n] == b'[' => Some(p + 1),
+                None => None,
+            },
+            Some(b'\\') if p + 1 < pattern.len() => {
+                if pattern[p + 1] == name[n] {
+                    Some(p + 2)
+                } else {
+                    None
+                }
+            }
+            Some(c) if *c == name[n] => Some(p + 1),
+            _ => None,
+        };
+
+        match (step, backtrack) {
+            (Some(next), _) => {
+                p = next;
+                n += 1;
+            }
+            (None, Some((bp, bn))) => {
+                backtrack = Some((bp, bn + 1));
+                p = bp;
+                n = bn + 1;
+            }
+            (None, None) => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+/// Match `c` against the bracket expression at the start of `pattern`.
+///
+/// Returns whether it matched and the length of the expression, or `None`, if the
+/// bracket is not closed.
+fn bracket(pattern: &[u8], c: u8) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negate = matches!(pattern.get(i), Some(b'!') | Some(b'^'));
+    if negate {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let lo = match pattern.get(i) {
+            None => return None,
+            Some(b']') if !first => break,
+            Some(b'\\') => {
+                i += 1;
+                *pattern.get(i)?
+            }
+            Some(lo) => *lo,
+        };
+        first = false;
+        i += 1;
+
+        if pattern.get(i) == Some(&b'-') && !matches!(pattern.get(i + 1), None | Some(b']')) {
+            let hi = pattern[i + 1];
+            i += 2;
+            matched |= lo <= c && c <= hi;
+        } else {
+            matched |= lo == c;
+        }
+    }
+
+    Some((matched != negate, i + 1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{alias_normalize, fnmatch, ModuleAliases};
+    use crate::modules::index::Index;
+    use std::ffi::OsString;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_fnmatch() {
+        assert!(fnmatch(
+            b"pci:v00008086d*sv*sd*bc*sc*i*",
+            b"pci:v00008086d00001234sv0sd0bc02sc00i00"
+        ));
+        assert!(!fnmatch(b"pci:v00008086d*", b"pci:v000010ECd00008168"));
+        assert!(fnmatch(b"usb:v0BDAp8153d*dc*", b"usb:v0BDAp8153d3100dc00"));
+        assert!(fnmatch(
+            b"dmi*:rvn[Ll][Ee][Nn][Oo][Vv][Oo]*",
+            b"dmi:bvnX:rvnLENOVO:"
+        ));
+        assert!(fnmatch(b"of:N*T*Cvendor,dev[0-9]", b"of:NxTyCvendor,dev7"));
+        assert!(!fnmatch(b"a[!0-9]", b"a5"));
+        assert!(fnmatch(b"a[]x]", b"a]"));
+        assert!(fnmatch(b"a\\*", b"a*"));
+        assert!(!fnmatch(b"a\\*", b"ab"));
+        assert!(fnmatch(b"*", b""));
+        assert!(!fnmatch(b"?", b""));
+        assert!(fnmatch(b"a[b", b"a[b"));
+    }
+
+    #[test]
+    fn test_alias_normalize() {
+        assert_eq!(alias_normalize(b"snd-hda[a-z]-x"), b"snd_hda[a-z]_x");
+    }
+
+    #[test]
+    fn test_module_aliases() {
+        let content = b"# Aliases extracted from modules themselves.\n\
+                        alias pci:v00008086d000015B8sv*sd*bc*sc*i* e1000e\n\
+                        alias pci:v00008086d*sv*sd*bc*sc*i* intel_generic\n\
+                        alias usb:v0BDAp8153d*dc*dsc*dp*ic*isc*ip*in* r8152\n\
+                        alias pci:v00008086d000015B8sv*sd*bc02sc*i* e1000e\n";
+        let os = |v: &[&str]| v.iter().map(OsString::from).collect::<Vec<_>>();
+
+        let text = ModuleAliases::parse(content);
+        assert_eq!(
+            text.lookup(b"pci:v00008086d000015B8sv00001028sd000007A1bc02sc00i00"),
+            os(&["e1000e", "intel_generic"])
+        );
+        assert_eq!(
+            text.lookup(b"usb:v0BDAp8153d3100dc00dsc00dp00icFFisc00ip00in00"),
+            os(&["r8152"])
+        );
+        assert!(text
+            .lookup(b"pci:v000010ECd00008168sv0sd0bc02sc00i00")
+            .is_empty());
+
+        // the binary index gives the same result
+        let tmp_dir = TempDir::new().unwrap();
+        let mut index = Index::default();
+        index.insert(b"pci:v00008086d000015B8sv*sd*bc*sc*i*", b"e1000e", 0);
+        index.insert(b"pci:v00008086d*sv*sd*bc*sc*i*", b"intel_generic", 1);
+        fs::write(tmp_dir.path().join("modules.alias.bin"), index.to_bytes()).unwrap();
+        let bin = ModuleAliases::from_kernel_dir(tmp_dir.path()).unwrap();
+        assert_eq!(
+            bin.lookup(b"pci:v00008086d000015B8sv00001028sd000007A1bc02sc00i00"),
+            os(&["e1000e", "intel_generic"])
+        );
+
+        assert!(ModuleAliases::from_kernel_dir(&tmp_dir.path().join("missing")).is_err());
+    }
+}
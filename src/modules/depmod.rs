@@ -0,0 +1,297 @@
+//! The `modules.*` metadata for the modules installed in a destination root
+//!
+//! The text files of the kernel module directory are filtered to the modules present
+//! in the destination and the binary indexes are generated from them, so `modprobe`
+//! works in the image without running `depmod`.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::os::unix::prelude::*;
+use std::path::Path;
+
+use hashbrown::{HashMap, HashSet};
+use walkdir::WalkDir;
+
+use super::index::Index;
+use super::modprobe::module_name_normalize;
+use crate::file::in_root;
+
+/// The file name extensions of uncompressed and compressed modules
+pub const MODULE_EXTENSIONS: &[&str] = &[".ko", ".ko.gz", ".ko.xz", ".ko.zst"];
+
+/// Files describing the modules built into the kernel, which are copied unchanged
+const BUILTIN_FILES: &[&str] = &[
+    "modules.builtin",
+    "modules.builtin.modinfo",
+    "modules.builtin.bin",
+    "modules.builtin.alias.bin",
+];
+
+/// The module name of a module path, like `snd_hda_intel` for
+/// `kernel/sound/pci/hda/snd-hda-intel.ko.xz`
+pub fn module_name(path: &[u8]) -> Option<Vec<u8>> {
+    let file_name = Path::new(OsStr::from_bytes(path)).file_name()?.as_bytes();
+    let name = MODULE_EXTENSIONS
+        .iter()
+        .find_map(|ext| file_name.strip_suffix(ext.as_bytes()))?;
+    Some(module_name_normalize(name))
+}
+
+/// The names of all modules installed below `kernel_dir` in `destroot`,
+/// including the modules of earlier runs
+pub fn installed_module_names(kernel_dir: &Path, destroot: &Path) -> HashSet<Vec<u8>> {
+    WalkDir::new(in_root(destroot, kernel_dir))
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| !e.file_type().is_dir())
+        .filter_map(|e| module_name(e.path().as_os_str().as_bytes()))
+        .collect()
+}
+
+/// Write the module metadata of `kernel_dir` for the modules installed in `destroot`.
+///
+/// Nothing is written, if `kernel_dir` has no `modules.dep` or no module is installed,
+/// and if the destination is `kernel_dir` itself, like for the destination root `/`.
+pub fn write_module_metadata(kernel_dir: &Path, destroot: &Path) -> io::Result<()> {
+    let dest_dir = in_root(destroot, kernel_dir);
+    let dep = match fs::read(kernel_dir.join("modules.dep")) {
+        Ok(d) => d,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !dest_dir.is_dir() || fs::canonicalize(&dest_dir)? == fs::canonicalize(kernel_dir)? {
+        return Ok(());
+    }
+
+    // the priority of a module is its position in modules.dep
+    let mut priorities = HashMap::<Vec<u8>, u32>::new();
+    let mut paths = HashSet::<Vec<u8>>::new();
+    let mut dep_index = Index::default();
+    let dep = filter_lines(&dep, |line| {
+        let path = match line.split(|c| *c == b':').next() {
+            Some(p) if !p.is_empty() => p,
+            _ => return false,
+        };
+        let installed = if path.starts_with(b"/") {
+            in_root(destroot, Path::new(OsStr::from_bytes(path)))
+        } else {
+            dest_dir.join(OsStr::from_bytes(path))
+        };
+        let name = match module_name(path) {
+            Some(n) if installed.exists() => n,
+            _ => return false,
+        };
+        let priority = priorities.len() as u32;
+        dep_index.insert(&name, line, priority);
+        priorities.insert(name, priority);
+        paths.insert(path.to_vec());
+        true
+    });
+    if priorities.is_empty() {
+        return Ok(());
+    }
+    fs::write(dest_dir.join("modules.dep"), dep)?;
+    fs::write(dest_dir.join("modules.dep.bin"), dep_index.to_bytes())?;
+
+    filter_file(kernel_dir, &dest_dir, "modules.order", |line| {
+        paths.contains(line)
+    })?;
+
+    // `alias <pattern> <module>` lines
+    for (file, bin) in [
+        ("modules.alias", "modules.alias.bin"),
+        ("modules.symbols", "modules.symbols.bin"),
+    ] {
+        let mut index = Index::default();
+        let written = filter_file(kernel_dir, &dest_dir, file, |line| {
+            let words = line.split(|c| *c == b' ').collect::<Vec<_>>();
+            match (
+                words.as_slice(),
+                words.get(2).and_then(|n| priorities.get(*n)),
+            ) {
+                ([b"alias", pattern, name], Some(priority)) => {
+                    index.insert(pattern, name, *priority);
+                    true
+                }
+                _ => false,
+            }
+        })?;
+        if written {
+            fs::write(dest_dir.join(bin), index.to_bytes())?;
+        }
+    }
+
+    let installed = |name: Option<&[u8]>| matches!(name, Some(n) if priorities.contains_key(n));
+
+    // `softdep <module> …` and `weakdep <module> …` lines
+    for file in ["modules.softdep", "modules.weakdep"] {
+        filter_file(kernel_dir, &dest_dir, file, |line| {
+            installed(line.split(|c| *c == b' ').nth(1))
+        })?;
+    }
+
+    // `<module> <devname> <type><major>:<minor>` lines
+    filter_file(kernel_dir, &dest_dir, "modules.devname", |line| {
+        installed(line.split(|c| *c == b' ').next())
+    })?;
+
+    for file in BUILTIN_FILES {
+        match fs::copy(kernel_dir.join(file), dest_dir.join(file)) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Keep the comments and the lines of `content` for which `keep` is true.
+fn filter_lines(content: &[u8], mut keep: impl FnMut(&[u8]) -> bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(content.len());
+    for line in content.split(|c| *c == b'\n') {
+        if line.starts_with(b"#") || (!line.is_empty() && keep(line)) {
+            out.extend_from_slice(line);
+            out.push(b'\n');
+        }
+    }
+    out
+}
+
+/// Filter the file `name` of `kernel_dir` to `dest_dir`.
+///
+/// Returns `false`, if `kernel_dir` has no such file.
+fn filter_file(
+    kernel_dir: &Path,
+    dest_dir: &Path,
+    name: &str,
+    keep: impl FnMut(&[u8]) -> bool,
+) -> io::Result<bool> {
+    let content = match fs::read(kernel_dir.join(name)) {
+        Ok(c) => c,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    fs::write(dest_dir.join(name), filter_lines(&content, keep))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{module_name, write_module_metadata};
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_module_name() {
+        assert_eq!(
+            module_name(b"kernel/sound/pci/hda/snd-hda-intel.ko.xz"),
+            Some(b"snd_hda_intel".to_vec())
+        );
+        assert_eq!(module_name(b"/updates/e1000e.ko"), Some(b"e1000e".to_vec()));
+        assert_eq!(module_name(b"kernel/modules.order"), None);
+    }
+
+    #[test]
+    fn test_write_module_metadata() {
+        let tmp_dir = TempDir::new().unwrap();
+        let kernel_dir = tmp_dir.path().join("lib/modules/1.0");
+        let destroot = tmp_dir.path().join("dest");
+        let dest_dir = destroot.join(kernel_dir.strip_prefix("/").unwrap());
+        fs::create_dir_all(kernel_dir.join("kernel")).unwrap();
+        fs::create_dir_all(dest_dir.join("kernel")).unwrap();
+        for m in ["a-mod.ko", "b.ko.xz", "c.ko"] {
+            fs::write(kernel_dir.join("kernel").join(m), "").unwrap();
+        }
+        for m in ["a-mod.ko", "c.ko"] {
+            fs::write(dest_dir.join("kernel").join(m), "").unwrap();
+        }
+
+        fs::write(
+            kernel_dir.join("modules.dep"),
+            "kernel/a-mod.ko: kernel/c.ko\nkernel/b.ko.xz: kernel/c.ko\nkernel/c.ko:\n",
+        )
+        .unwrap();
+        fs::write(
+            kernel_dir.join("modules.order"),
+            "kernel/c.ko\nkernel/b.ko.xz\nkernel/a-mod.ko\n",
+        )
+        .unwrap();
+        fs::write(
+            kernel_dir.join("modules.alias"),
+            "# Aliases extracted from modules themselves.\n\
+             alias pci:v00008086d00001234sv*sd*bc*sc*i* a_mod\n\
+             alias pci:v00008086d*sv*sd*bc*sc*i* b\n\
+             alias usb:v1234p* c\n",
+        )
+        .unwrap();
+        fs::write(
+            kernel_dir.join("modules.softdep"),
+            "# Soft dependencies extracted from modules themselves.\n\
+             softdep a_mod pre: c\nsoftdep b post: c\n",
+        )
+        .unwrap();
+        fs::write(kernel_dir.join("modules.builtin"), "kernel/ext4.ko\n").unwrap();
+
+        // the module directory of the host is left alone
+        write_module_metadata(&kernel_dir, Path::new("/")).unwrap();
+        assert_eq!(
+            fs::read_to_string(kernel_dir.join("modules.dep")).unwrap(),
+            "kernel/a-mod.ko: kernel/c.ko\nkernel/b.ko.xz: kernel/c.ko\nkernel/c.ko:\n"
+        );
+        assert!(!kernel_dir.join("modules.dep.bin").exists());
+
+        write_module_metadata(&kernel_dir, &destroot).unwrap();
+
+        let read = |name: &str| fs::read_to_string(dest_dir.join(name)).unwrap();
+        assert_eq!(
+            read("modules.dep"),
+            "kernel/a-mod.ko: kernel/c.ko\nkernel/c.ko:\n"
+        );
+        assert_eq!(read("modules.order"), "kernel/c.ko\nkernel/a-mod.ko\n");
+        assert_eq!(
+            read("modules.alias"),
+            "# Aliases extracted from modules themselves.\n\
+             alias pci:v00008086d00001234sv*sd*bc*sc*i* a_mod\n\
+             alias usb:v1234p* c\n"
+        );
+        assert_eq!(
+            read("modules.softdep"),
+            "# Soft dependencies extracted from modules themselves.\n\
+             softdep a_mod pre: c\n"
+        );
+        assert_eq!(read("modules.builtin"), "kernel/ext4.ko\n");
+        assert!(!dest_dir.join("modules.symbols").exists());
+
+        // libkmod finds the modules by the generated indexes
+        let kmod_ctx = kmod::Context::new_with(Some(dest_dir.as_os_str()), None).unwrap();
+        let lookup = |alias: &str| {
+            kmod_ctx
+                .module_new_from_lookup(OsStr::new(alias))
+                .unwrap()
+                .map(|m| m.name().unwrap().to_os_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            lookup("pci:v00008086d00001234sv00000000sd00000000bc02sc00i00"),
+            ["a_mod"]
+        );
+        assert!(lookup("pci:v00008086d00005678sv00000000sd00000000bc02sc00i00").is_empty());
+        assert_eq!(lookup("usb:v1234p0001"), ["c"]);
+
+        let a = kmod_ctx
+            .module_new_from_lookup(OsStr::new("a-mod"))
+            .unwrap()
+            .next()
+            .unwrap();
+        assert_eq!(
+            a.dependencies()
+                .map(|m| m.name().unwrap().to_os_string())
+                .collect::<Vec<_>>(),
+            ["c"]
+        );
+    }
+}
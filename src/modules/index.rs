@@ -0,0 +1,277 @@
+//! The binary indexes of `depmod`, like `modules.dep.bin` and `modules.alias.bin`
+//!
+//! An index is a trie of the keys with prefix compression, written children first:
+//! * the magic, the version and the offset of the root node, as big endian `u32`
+//! * a node is the NUL terminated prefix, the range of its child characters with the
+//!   offsets of the children and the values sorted by priority
+//!
+//! The flags in the upper bits of a node offset tell, which of the parts are present.
+
+use std::collections::BTreeMap;
+use std::io;
+
+const INDEX_MAGIC: u32 = 0xB007_F457;
+const INDEX_VERSION: u32 = 0x0002_0001;
+const INDEX_NODE_PREFIX: u32 = 0x8000_0000;
+const INDEX_NODE_VALUES: u32 = 0x4000_0000;
+const INDEX_NODE_CHILDS: u32 = 0x2000_0000;
+const INDEX_NODE_MASK: u32 = 0x0FFF_FFFF;
+/// child characters are 7 bit
+const INDEX_CHILDMAX: u8 = 128;
+
+/// The values of a key with their priority
+pub type Values = Vec<(u32, Vec<u8>)>;
+
+#[derive(Debug, Default)]
+struct Node {
+    children: BTreeMap<u8, Node>,
+    values: Values,
+}
+
+#[derive(Debug, Default)]
+pub struct Index {
+    root: Node,
+}
+
+impl Index {
+    /// Add `value` for `key`, values of a key are sorted by ascending `priority`.
+    ///
+    /// Returns `false` for keys with non-ASCII characters, which are skipped like
+    /// `depmod` does.
+    pub fn insert(&mut self, key: &[u8], value: &[u8], priority: u32) -> bool {
+        if key.iter().any(|c| *c >= INDEX_CHILDMAX || *c == 0) {
+            return false;
+        }
+
+        let mut node = &mut self.root;
+        for c in key {
+            node = node.children.entry(*c).or_default();
+        }
+        if !node.values.iter().any(|(_, v)| v == value) {
+            let pos = node
+                .values
+                .iter()
+                .position(|(p, _)| *p > priority)
+                .unwrap_or(node.values.len());
+            node.values.insert(pos, (priority, value.to_vec()));
+        }
+        true
+    }
+
+    /// Read an index written by `depmod` or [`Index::to_bytes`]
+    pub fn from_bytes(data: &[u8]) -> io::Result<Index> {
+        let word = |pos: usize| -> io::Result<u32> {
+            data.get(pos..pos + 4)
+                .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+                .ok_or_else(|| invalid("truncated index"))
+        };
+        if word(0)? != INDEX_MAGIC {
+            return Err(invalid("no index magic"));
+        }
+        if word(4)? >> 16 != INDEX_VERSION >> 16 {
+            return Err(invalid("unsupported index version"));
+        }
+
+        let mut index = Index::default();
+        read_node(data, word(8)?, &mut index.root, 0)?;
+        Ok(index)
+    }
+
+    /// All keys with their values, sorted by key
+    pub fn entries(&self) -> Vec<(Vec<u8>, Values)> {
+        fn walk(node: &Node, key: &mut Vec<u8>, out: &mut Vec<(Vec<u8>, Values)>) {
+            if !node.values.is_empty() {
+                out.push((key.clone(), node.values.clone()));
+            }
+            for (c, child) in node.children.iter() {
+                key.push(*c);
+                walk(child, key, out);
+                key.pop();
+            }
+        }
+
+        let mut out = Vec::new();
+        walk(&self.root, &mut Vec::new(), &mut out);
+        out
+    }
+
+    /// The index in the binary format `libkmod` reads
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&INDEX_MAGIC.to_be_bytes());
+        out.extend_from_slice(&INDEX_VERSION.to_be_bytes());
+        out.extend_from_slice(&0u32.to_be_bytes());
+        let root = write_node(&self.root, &mut out);
+        out[8..12].copy_from_slice(&root.to_be_bytes());
+        out
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Read the node at `offset` into `node`, expanding its prefix to a chain of children.
+fn read_node(data: &[u8], offset: u32, mut node: &mut Node, depth: usize) -> io::Result<()> {
+    // a key can not be longer than the index
+    if depth > data.len() {
+        return Err(invalid("index loop"));
+    }
+
+    let mut pos = (offset & INDEX_NODE_MASK) as usize;
+    let take = |pos: &mut usize, len: usize| -> io::Result<&[u8]> {
+        let b = data
+            .get(*pos..*pos + len)
+            .ok_or_else(|| invalid("truncated index"))?;
+        *pos += len;
+        Ok(b)
+    };
+    let string = |pos: &mut usize| -> io::Result<Vec<u8>> {
+        let len = data
+            .get(*pos..)
+            .and_then(|d| d.iter().position(|c| *c == 0))
+            .ok_or_else(|| invalid("truncated index"))?;
+        let s = data[*pos..*pos + len].to_vec();
+        *pos += len + 1;
+        Ok(s)
+    };
+    let word = |pos: &mut usize| -> io::Result<u32> {
+        let b = take(pos, 4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    };
+
+    let mut depth = depth;
+    if offset & INDEX_NODE_PREFIX != 0 {
+        for c in string(&mut pos)? {
+            node = node.children.entry(c).or_default();
+            depth += 1;
+        }
+    }
+
+    if offset & INDEX_NODE_CHILDS != 0 {
+        let range = take(&mut pos, 2)?;
+        let (first, last) = (range[0], range[1]);
+        if first > last {
+            return Err(invalid("invalid child range"));
+        }
+        for c in first..=last {
+            let child = word(&mut pos)?;
+            if child != 0 {
+                read_node(data, child, node.children.entry(c).or_default(), depth + 1)?;
+            }
+        }
+    }
+
+    if offset & INDEX_NODE_VALUES != 0 {
+        for _ in 0..word(&mut pos)? {
+            let priority = word(&mut pos)?;
+            node.values.push((priority, string(&mut pos)?));
+        }
+    }
+
+    Ok(())
+}
+
+fn write_node(mut node: &Node, out: &mut Vec<u8>) -> u32 {
+    // collapse chains of single children without values into the prefix
+    let mut prefix = Vec::new();
+    while node.values.is_empty() && node.children.len() == 1 {
+        let (c, child) = node.children.iter().next().unwrap();
+        prefix.push(*c);
+        node = child;
+    }
+
+    let children = node
+        .children
+        .iter()
+        .map(|(c, child)| (*c, write_node(child, out)))
+        .collect::<Vec<_>>();
+
+    let mut offset = out.len() as u32;
+
+    if !prefix.is_empty() {
+        out.extend_from_slice(&prefix);
+        out.push(0);
+        offset |= INDEX_NODE_PREFIX;
+    }
+
+    if let (Some((first, _)), Some((last, _))) = (children.first(), children.last()) {
+        out.push(*first);
+        out.push(*last);
+        let mut offsets = vec![0u32; (*last - *first) as usize + 1];
+        for (c, child) in children.iter() {
+            offsets[(*c - *first) as usize] = *child;
+        }
+        for o in offsets {
+            out.extend_from_slice(&o.to_be_bytes());
+        }
+        offset |= INDEX_NODE_CHILDS;
+    }
+
+    if !node.values.is_empty() {
+        out.extend_from_slice(&(node.values.len() as u32).to_be_bytes());
+        for (priority, value) in node.values.iter() {
+            out.extend_from_slice(&priority.to_be_bytes());
+            out.extend_from_slice(value);
+            out.push(0);
+        }
+        offset |= INDEX_NODE_VALUES;
+    }
+
+    offset
+}
+
+#[cfg(test)]
+mod test {
+    use super::Index;
+
+    #[test]
+    fn test_index() {
+        let mut index = Index::default();
+        assert!(index.insert(b"ab", b"x", 1));
+        assert!(index.insert(b"ab", b"y", 0));
+        assert!(index.insert(b"ab", b"x", 2));
+        assert!(!index.insert("ä".as_bytes(), b"z", 0));
+
+        #[rustfmt::skip]
+        let expected: &[u8] = &[
+            0xb0, 0x07, 0xf4, 0x57, 0x00, 0x02, 0x00, 0x01, 0xc0, 0x00, 0x00, 0x0c,
+            // root "ab" with the values y and x
+            b'a', b'b', 0,
+            0, 0, 0, 2,
+            0, 0, 0, 0, b'y', 0,
+            0, 0, 0, 1, b'x', 0,
+        ];
+        assert_eq!(index.to_bytes(), expected);
+
+        let mut index = Index::default();
+        index.insert(b"a", b"1", 0);
+        index.insert(b"c", b"2", 0);
+
+        #[rustfmt::skip]
+        let expected: &[u8] = &[
+            0xb0, 0x07, 0xf4, 0x57, 0x00, 0x02, 0x00, 0x01, 0x20, 0x00, 0x00, 0x20,
+            // "a"
+            0, 0, 0, 1, 0, 0, 0, 0, b'1', 0,
+            // "c"
+            0, 0, 0, 1, 0, 0, 0, 0, b'2', 0,
+            // root with the children 'a' to 'c'
+            b'a', b'c',
+            0x40, 0, 0, 0x0c,
+            0, 0, 0, 0,
+            0x40, 0, 0, 0x16,
+        ];
+        assert_eq!(index.to_bytes(), expected);
+
+        let read = Index::from_bytes(expected).unwrap();
+        assert_eq!(
+            read.entries(),
+            vec![
+                (b"a".to_vec(), vec![(0, b"1".to_vec())]),
+                (b"c".to_vec(), vec![(0, b"2".to_vec())]),
+            ]
+        );
+        assert_eq!(read.to_bytes(), expected);
+        assert!(Index::from_bytes(&expected[..expected.len() - 2]).is_err());
+    }
+}
@@ -1,35 +1,139 @@
+use std::collections::BTreeSet;
 use std::ffi::OsStr;
 use std::ffi::OsString;
-use std::fs::File;
-use std::io::{BufReader, Read};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
 use std::os::unix::prelude::*;
+use std::path::{Path, PathBuf};
 
 use hashbrown::HashSet;
 
 use walkdir::WalkDir;
 
-pub fn modalias_list(
-) -> Result<HashSet<OsString>, Box<dyn std::error::Error + 'static + Send + Sync>> {
-    let mut modules: HashSet<OsString> = HashSet::new();
+use alias::ModuleAliases;
+use depmod::module_name;
+
+pub mod alias;
+pub mod depmod;
+mod index;
+pub mod modprobe;
+pub mod net;
+pub mod storage;
+
+/// The modules needed for the devices and loaded modules of the running system
+#[derive(Debug, Default)]
+pub struct ModaliasReport {
+    /// the module names in the target kernel
+    pub modules: BTreeSet<OsString>,
+    /// loaded modules without a module of that name or alias in the target kernel
+    pub missing_modules: BTreeSet<OsString>,
+    /// device modaliases the running kernel has a module for, but the target kernel not
+    pub unmatched_aliases: BTreeSet<OsString>,
+}
+
+/// Restricts the devices, whose modaliases are resolved
+#[derive(Debug, Clone, Default)]
+pub struct DeviceFilter {
+    /// the subsystems or buses of the devices, like `pci`, `usb` or `virtio`
+    pub subsystems: Vec<OsString>,
+    /// device paths like `/devices/pci0000:00/0000:00:1f.2`, relative to the sysfs root
+    pub path_prefixes: Vec<PathBuf>,
+}
+
+impl DeviceFilter {
+    pub fn is_empty(&self) -> bool {
+        self.subsystems.is_empty() && self.path_prefixes.is_empty()
+    }
+
+    /// Whether the device `dir` with `modalias` belongs to one of the subsystems.
+    ///
+    /// Both the `subsystem` link of the device and the bus prefix of the modalias are
+    /// matched, like `platform` and `acpi` for `acpi:PNP0C0A:`.
+    fn matches_subsystem(&self, dir: &Path, modalias: &[u8]) -> bool {
+        if self.subsystems.is_empty() {
+            return true;
+        }
+        let subsystem = fs::read_link(dir.join("subsystem"))
+            .ok()
+            .and_then(|link| link.file_name().map(OsStr::to_os_string));
+        let bus = modalias
+            .iter()
+            .position(|c| *c == b':')
+            .map(|i| OsStr::from_bytes(&modalias[..i]).to_os_string());
+        self.subsystems
+            .iter()
+            .any(|s| Some(s) == subsystem.as_ref() || Some(s) == bus.as_ref())
+    }
+
+    /// The directories below `sysfs` to search for devices
+    fn roots(&self, sysfs: &Path) -> Vec<PathBuf> {
+        if self.path_prefixes.is_empty() {
+            return vec![sysfs.join("devices")];
+        }
+        self.path_prefixes
+            .iter()
+            .map(|p| sysfs.join(p.strip_prefix("/").unwrap_or(p)))
+            .collect()
+    }
+}
 
-    let kmod_ctx = kmod::Context::new()?;
+/// Resolve the loaded modules and the device modaliases of the running system against
+/// the module index of `kernel_dir`.
+///
+/// The devices are searched in `sysfs`, normally `/sys`, the loaded modules in
+/// `procfs`, normally `/proc`. With a non-empty `filter` only the matching devices
+/// are resolved and the loaded modules are left out.
+///
+/// `running_kernel_dir` is the module directory of the running kernel, to report the
+/// device modaliases which lost their module in the target kernel.
+pub fn modalias_list(
+    kernel_dir: &Path,
+    running_kernel_dir: Option<&Path>,
+    sysfs: &Path,
+    procfs: &Path,
+    filter: &DeviceFilter,
+) -> Result<ModaliasReport, Box<dyn std::error::Error + 'static + Send + Sync>> {
+    let mut report = ModaliasReport::default();
 
-    for m in kmod_ctx.modules_loaded()? {
-        let module_name = m
-            .name()
-            .ok_or(format!("Module {:?} has no name", m.path()))?;
-        modules.insert(module_name.to_os_string());
+    let aliases = ModuleAliases::from_kernel_dir(kernel_dir)
+        .map_err(|e| format!("Reading the module aliases of {:?}: {}", kernel_dir, e))?;
+    let known = kernel_module_names(kernel_dir)?;
+    let running = running_kernel_dir
+        .filter(|dir| *dir != kernel_dir)
+        .and_then(|dir| ModuleAliases::from_kernel_dir(dir).ok());
 
-        for k in kmod_ctx.module_new_from_lookup(&OsString::from(module_name))? {
-            let module_name = k
-                .name()
-                .ok_or(format!("Module {:?} has no name", k.path().clone()))?;
-            modules.insert(module_name.to_os_string());
+    let loaded = if filter.is_empty() {
+        let modules = procfs.join("modules");
+        match fs::read(&modules) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(format!("Reading {:?}: {}", modules, e).into()),
         }
+    } else {
+        Vec::new()
+    };
+    for name in loaded
+        .split(|c| *c == b'\n')
+        .filter_map(|line| line.split(|c| *c == b' ').next())
+        .filter(|name| !name.is_empty())
+    {
+        let name = OsStr::from_bytes(name);
+        if known.contains(name.as_bytes()) {
+            report.modules.insert(name.to_os_string());
+            continue;
+        }
+        // a renamed module keeps its old name as alias
+        let modules = aliases.lookup(name.as_bytes());
+        if modules.is_empty() {
+            report.missing_modules.insert(name.to_os_string());
+        }
+        report.modules.extend(modules);
     }
 
-    for entry in WalkDir::new("/sys/devices")
-        .into_iter()
+    for entry in filter
+        .roots(sysfs)
+        .iter()
+        .flat_map(|root| WalkDir::new(root).into_iter())
         .filter_map(std::result::Result::<_, _>::ok)
         .filter(|e| e.file_name().as_bytes().eq(b"modalias"))
     {
@@ -43,14 +147,239 @@ pub fn modalias_list(
         if modalias.is_empty() {
             continue;
         }
-        let modalias = OsStr::from_bytes(&modalias);
+        if !filter.matches_subsystem(entry.path().parent().unwrap(), &modalias) {
+            continue;
+        }
 
-        for m in kmod_ctx.module_new_from_lookup(modalias)? {
-            let module_name = m
-                .name()
-                .ok_or(format!("Module {:?} has no name", m.path()))?;
-            modules.insert(module_name.to_os_string());
+        let modules = aliases.lookup(&modalias);
+        if modules.is_empty() {
+            if let Some(ref running) = running {
+                if !running.lookup(&modalias).is_empty() {
+                    report
+                        .unmatched_aliases
+                        .insert(OsString::from_vec(modalias));
+                }
+            }
         }
+        report.modules.extend(modules);
+    }
+    Ok(report)
+}
+
+/// The module of the driver bound to the sysfs device `dir`, `None` for builtin drivers
+fn driver_module(dir: &Path) -> Option<OsString> {
+    fs::read_link(dir.join("driver/module"))
+        .ok()?
+        .file_name()
+        .map(OsStr::to_os_string)
+}
+
+/// Read a sysfs attribute without the trailing newline
+fn attribute(dir: &Path, name: &str) -> Option<Vec<u8>> {
+    let mut value = fs::read(dir.join(name)).ok()?;
+    while value.last() == Some(&b'\n') {
+        value.pop();
+    }
+    Some(value)
+}
+
+/// The names of the loadable and builtin modules of `kernel_dir`
+fn kernel_module_names(
+    kernel_dir: &Path,
+) -> Result<HashSet<Vec<u8>>, Box<dyn std::error::Error + 'static + Send + Sync>> {
+    let mut names = HashSet::new();
+    for (file, required) in [("modules.dep", true), ("modules.builtin", false)] {
+        let content = match fs::read(kernel_dir.join(file)) {
+            Ok(c) => c,
+            Err(e) if !required && e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(format!("Reading {:?}: {}", kernel_dir.join(file), e).into()),
+        };
+        names.extend(
+            content
+                .split(|c| *c == b'\n')
+                .filter_map(|line| line.split(|c| *c == b':').next())
+                .filter_map(module_name),
+        );
+    }
+    Ok(names)
+}
+
+/// Builders of a fake sysfs for the tests
+#[cfg(test)]
+mod fixture {
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::path::Path;
+
+    pub(super) fn mkdir(dir: impl AsRef<Path>) {
+        fs::create_dir_all(dir).unwrap();
+    }
+
+    pub(super) fn write(file: impl AsRef<Path>, content: &str) {
+        let file = file.as_ref();
+        mkdir(file.parent().unwrap());
+        fs::write(file, content).unwrap();
+    }
+
+    pub(super) fn link(target: impl AsRef<Path>, link: impl AsRef<Path>) {
+        let link = link.as_ref();
+        mkdir(link.parent().unwrap());
+        symlink(target, link).unwrap();
+    }
+
+    /// Bind the device `dir` to a driver of the module `module`
+    pub(super) fn driver(sysfs: &Path, dir: &Path, module: &str) {
+        mkdir(dir);
+        link(sysfs.join("module").join(module), dir.join("driver/module"));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{kernel_module_names, modalias_list, DeviceFilter};
+    use std::ffi::OsString;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_kernel_module_names() {
+        let tmp_dir = TempDir::new().unwrap();
+        let kernel_dir = tmp_dir.path();
+        fs::write(
+            kernel_dir.join("modules.dep"),
+            "kernel/drivers/net/r8169.ko.xz: kernel/phy/realtek.ko\nkernel/phy/realtek.ko:\n",
+        )
+        .unwrap();
+        fs::write(
+            kernel_dir.join("modules.builtin"),
+            "kernel/fs/ext4/ext4.ko\n",
+        )
+        .unwrap();
+
+        let mut names = kernel_module_names(kernel_dir)
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, [&b"ext4"[..], b"r8169", b"realtek"]);
+    }
+
+    #[test]
+    fn test_modalias_list() {
+        let tmp_dir = TempDir::new().unwrap();
+        let kernel_dir = tmp_dir.path().join("modules/2.0");
+        let running_dir = tmp_dir.path().join("modules/1.0");
+        let sysfs = tmp_dir.path().join("sys");
+        let procfs = tmp_dir.path().join("proc");
+        assert!(
+            modalias_list(&kernel_dir, None, &sysfs, &procfs, &DeviceFilter::default()).is_err()
+        );
+
+        fs::create_dir_all(&kernel_dir).unwrap();
+        fs::create_dir_all(&running_dir).unwrap();
+        fs::write(
+            kernel_dir.join("modules.dep"),
+            "kernel/r8169.ko:\nkernel/button.ko:\nkernel/xhci-pci.ko:\nkernel/loop.ko:\n",
+        )
+        .unwrap();
+        fs::write(
+            kernel_dir.join("modules.alias"),
+            "alias pci:v000010ECd00008168sv*sd*bc*sc*i* r8169\n\
+             alias acpi*:PNP0C0C:* button\n\
+             alias pci:v*d*sv*sd*bc0Csc03i30* xhci_pci\n\
+             alias r8168 r8169\n",
+        )
+        .unwrap();
+        fs::write(
+            running_dir.join("modules.alias"),
+            "alias pci:v00008086d00001234sv*sd*bc*sc*i* oldnic\n",
+        )
+        .unwrap();
+
+        let device = |path: &str, subsystem: &str, modalias: &str| {
+            let dir = sysfs.join("devices").join(path);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("modalias"), format!("{}\n", modalias)).unwrap();
+            symlink(sysfs.join("bus").join(subsystem), dir.join("subsystem")).unwrap();
+        };
+        device(
+            "pci0000:00/0000:00:1c.0/0000:01:00.0",
+            "pci",
+            "pci:v000010ECd00008168sv00001043sd00008677bc02sc00i00",
+        );
+        device(
+            "pci0000:00/0000:00:14.0",
+            "pci",
+            "pci:v00008086d0000A36Dsv00001043sd00008694bc0Csc03i30",
+        );
+        device(
+            "pci0000:00/0000:00:1f.6",
+            "pci",
+            "pci:v00008086d00001234sv00001043sd00008694bc02sc00i00",
+        );
+        device("LNXSYSTM:00/PNP0C0C:00", "acpi", "acpi:PNP0C0C:");
+
+        fs::create_dir_all(&procfs).unwrap();
+        fs::write(
+            procfs.join("modules"),
+            "loop 32768 0 - Live 0x0000000000000000\n\
+             r8168 106496 0 - Live 0x0000000000000000\n\
+             oldnic 65536 0 - Live 0x0000000000000000\n",
+        )
+        .unwrap();
+
+        let list = |filter: &DeviceFilter| {
+            modalias_list(&kernel_dir, Some(&running_dir), &sysfs, &procfs, filter).unwrap()
+        };
+        let os = |v: &[&str]| v.iter().map(OsString::from).collect::<Vec<_>>();
+
+        let report = list(&DeviceFilter {
+            subsystems: os(&["pci"]),
+            ..Default::default()
+        });
+        assert_eq!(
+            report.modules.into_iter().collect::<Vec<_>>(),
+            os(&["r8169", "xhci_pci"])
+        );
+        assert_eq!(
+            report.unmatched_aliases.into_iter().collect::<Vec<_>>(),
+            os(&["pci:v00008086d00001234sv00001043sd00008694bc02sc00i00"])
+        );
+
+        let report = list(&DeviceFilter {
+            path_prefixes: vec![
+                PathBuf::from("/devices/pci0000:00/0000:00:1c.0"),
+                PathBuf::from("devices/LNXSYSTM:00"),
+            ],
+            ..Default::default()
+        });
+        assert_eq!(
+            report.modules.into_iter().collect::<Vec<_>>(),
+            os(&["button", "r8169"])
+        );
+        assert!(report.unmatched_aliases.is_empty());
+
+        let report = list(&DeviceFilter {
+            subsystems: os(&["usb"]),
+            path_prefixes: vec![PathBuf::from("/devices/pci0000:00")],
+        });
+        assert!(report.modules.is_empty());
+
+        // the loaded modules are resolved as well, a renamed module by its old name
+        let report = list(&DeviceFilter::default());
+        assert_eq!(
+            report.modules.into_iter().collect::<Vec<_>>(),
+            os(&["button", "loop", "r8169", "xhci_pci"])
+        );
+        assert_eq!(
+            report.missing_modules.into_iter().collect::<Vec<_>>(),
+            os(&["oldnic"])
+        );
+        assert_eq!(
+            report.unmatched_aliases.into_iter().collect::<Vec<_>>(),
+            os(&["pci:v00008086d00001234sv00001043sd00008694bc02sc00i00"])
+        );
     }
-    Ok(modules)
 }
@@ -0,0 +1,351 @@
+//! The `modprobe.d` configuration of the host, as parsed by libkmod
+//!
+//! Blacklisted modules are not installed for aliases, soft and weak dependencies, and
+//! the programs run by the `install` and `remove` commands of the installed modules
+//! are installed with them. The options, aliases, soft dependencies and blacklists of the installed
+//! modules can be written to a `modprobe.d` file of the image.
+
+use std::collections::BTreeSet;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::os::unix::prelude::*;
+use std::path::Path;
+
+use hashbrown::HashSet;
+
+use crate::file::in_root;
+
+/// The file in the destination root with the configuration of the installed modules
+pub const MODPROBE_CONF: &str = "etc/modprobe.d/dracut-install.conf";
+
+/// The configuration relevant for installing modules
+#[derive(Debug, Clone, Default)]
+pub struct ModprobeConfig {
+    /// the names of the blacklisted modules
+    pub blacklists: BTreeSet<String>,
+    /// the `install` commands by module name
+    pub install_commands: Vec<(String, OsString)>,
+    /// the `remove` commands by module name
+    pub remove_commands: Vec<(String, OsString)>,
+    /// the aliases with their module name
+    pub aliases: Vec<(String, OsString)>,
+    /// the module options by module name
+    pub options: Vec<(String, OsString)>,
+    /// the `pre:` and `post:` soft dependencies by module name
+    pub softdeps: Vec<(String, OsString)>,
+}
+
+impl ModprobeConfig {
+    /// Read the configuration of `kmod_ctx`
+    pub fn from_kmod(kmod_ctx: &kmod::Context) -> kmod::Result<ModprobeConfig> {
+        Ok(ModprobeConfig {
+            blacklists: kmod_ctx.config_blacklists()?.map(|(m, _)| m).collect(),
+            install_commands: kmod_ctx.config_install_commands()?.collect(),
+            remove_commands: kmod_ctx.config_remove_commands()?.collect(),
+            aliases: kmod_ctx.config_aliases()?.collect(),
+            options: kmod_ctx.config_options()?.collect(),
+            softdeps: kmod_ctx.config_softdeps()?.collect(),
+        })
+    }
+
+    /// The `modprobe.d` lines for `modules`, empty if there are none
+    pub fn to_modprobe_d(&self, modules: &HashSet<Vec<u8>>) -> String {
+        let installed = |name: &[u8]| modules.contains(&module_name_normalize(name));
+        let mut out = String::new();
+
+        for name in self.blacklists.iter().filter(|m| installed(m.as_bytes())) {
+            out.push_str(&format!("blacklist {}\n", name));
+        }
+        for (alias, name) in self.aliases.iter() {
+            if installed(name.as_bytes()) {
+                out.push_str(&format!("alias {} {}\n", alias, name.to_string_lossy()));
+            }
+        }
+        for (kind, entries) in [("options", &self.options), ("softdep", &self.softdeps)] {
+            for (name, value) in entries.iter() {
+                if installed(name.as_bytes()) && !value.is_empty() {
+                    out.push_str(&format!("{} {} {}\n", kind, name, value.to_string_lossy()));
+                }
+            }
+        }
+
+        if !out.is_empty() {
+            out.insert_str(
+                0,
+                "# The modprobe.d configuration of the host for the installed modules\n",
+            );
+        }
+        out
+    }
+
+    /// Write the configuration of `modules` to [`MODPROBE_CONF`] in `destroot`.
+    ///
+    /// `modules` are all modules installed in `destroot`, the file is replaced.
+    /// If no configuration applies to the modules, the file of an earlier run is removed.
+    pub fn write(&self, modules: &HashSet<Vec<u8>>, destroot: &Path) -> io::Result<()> {
+        let content = self.to_modprobe_d(modules);
+        let file = destroot.join(MODPROBE_CONF);
+        if content.is_empty() {
+            return match fs::remove_file(&file) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(file, content)
+    }
+
+    /// Check, if the module `name` is blacklisted, with `-` and `_` treated alike
+    pub fn is_blacklisted(&self, name: &OsStr) -> bool {
+        let name = String::from_utf8_lossy(&module_name_normalize(name.as_bytes())).into_owned();
+        self.blacklists.contains(&name)
+    }
+
+    /// The programs run by the `install` and `remove` commands of `modules`.
+    ///
+    /// Program names without a `/` are searched in `pathdirs` inside `sysroot`,
+    /// programs which do not exist inside `sysroot` are left out.
+    pub fn install_programs(
+        &self,
+        modules: &HashSet<Vec<u8>>,
+        pathdirs: &[OsString],
+        sysroot: &Path,
+    ) -> Vec<OsString> {
+        let mut programs = Vec::<OsString>::new();
+        for (_, command) in self
+            .install_commands
+            .iter()
+            .chain(self.remove_commands.iter())
+            .filter(|(m, _)| modules.contains(m.as_bytes()))
+        {
+            for program in command_programs(command.as_bytes()) {
+                let program = OsStr::from_bytes(&program);
+                let found = if program.as_bytes().contains(&b'/') {
+                    Some(program.to_os_string())
+                        .filter(|p| in_root(sysroot, Path::new(p)).is_file())
+                } else {
+                    pathdirs
+                        .iter()
+                        .map(|dir| Path::new(dir).join(program))
+                        .find(|p| in_root(sysroot, p).is_file())
+                        .map(|p| p.into_os_string())
+                };
+                match found {
+                    Some(p) if !programs.contains(&p) => programs.push(p),
+                    _ => {}
+                }
+            }
+        }
+        programs
+    }
+}
+
+/// Replace `-` by `_`, like libkmod does for module names.
+pub fn module_name_normalize(name: &[u8]) -> Vec<u8> {
+    name.iter()
+        .map(|c| if *c == b'-' { b'_' } else { *c })
+        .collect()
+}
+
+/// The programs of the simple commands in the shell command line `command`
+///
+/// Variable assignments, redirections, variables and shell builtins are skipped.
+pub fn command_programs(command: &[u8]) -> Vec<Vec<u8>> {
+    // words after which a command follows
+    const PREFIXES: &[&[u8]] = &[
+        b"exec", b"command", b"!", b"{", b"(", b"if", b"then", b"else", b"elif", b"do", b"while",
+        b"until",
+    ];
+    const BUILTINS: &[&[u8]] = &[
+        b"test", b"[", b"true", b"false", b"echo", b"printf", b"cd", b":", b"}", b")", b"fi",
+        b"done", b"return", b"exit",
+    ];
+
+    let mut programs = Vec::new();
+    let mut at_start = true;
+    for word in command
+        .split(|c| c.is_ascii_whitespace())
+        .filter(|w| !w.is_empty())
+    {
+        if matches!(word, b";" | b"&&" | b"||" | b"|" | b"&") {
+            at_start = true;
+            continue;
+        }
+        let (word, end) = match word.strip_suffix(b";") {
+            Some(w) => (w, true),
+            None => (word, false),
+        };
+
+        let word = word.strip_prefix(b"$(").unwrap_or(word);
+        if at_start
+            && !word.is_empty()
+            && !word.contains(&b'=')
+            && !word.contains(&b'>')
+            && !word.contains(&b'<')
+            && !PREFIXES.contains(&word)
+        {
+            at_start = false;
+            if !BUILTINS.contains(&word) && !word.starts_with(b"$") && !word.starts_with(b"-") {
+                programs.push(word.to_vec());
+            }
+        }
+        at_start |= end;
+    }
+    programs
+}
+
+#[cfg(test)]
+mod test {
+    use super::{command_programs, ModprobeConfig, MODPROBE_CONF};
+    use crate::modules::depmod::installed_module_names;
+    use hashbrown::HashSet;
+    use std::ffi::{OsStr, OsString};
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_command_programs() {
+        assert_eq!(
+            command_programs(
+                b"/sbin/modprobe --ignore-install snd $CMDLINE_OPTS && { /usr/sbin/alsactl restore >/dev/null 2>&1 || :; }"
+            ),
+            [&b"/sbin/modprobe"[..], b"/usr/sbin/alsactl"]
+        );
+        assert_eq!(
+            command_programs(b"LANG=C exec modprobe -i $MODPROBE_MODULE; true"),
+            [b"modprobe"]
+        );
+        assert_eq!(command_programs(b"/bin/true"), [b"/bin/true"]);
+        assert_eq!(
+            command_programs(b"echo 1 > /sys/foo; test -e /x || /sbin/foo"),
+            [b"/sbin/foo"]
+        );
+    }
+
+    #[test]
+    fn test_install_programs() {
+        let tmp_dir = TempDir::new().unwrap();
+        let sysroot = tmp_dir.path();
+        fs::create_dir_all(sysroot.join("usr/sbin")).unwrap();
+        fs::write(sysroot.join("usr/sbin/modprobe"), "").unwrap();
+        fs::write(sysroot.join("usr/sbin/fake-helper"), "").unwrap();
+        fs::write(sysroot.join("usr/sbin/missing-cleanup"), "").unwrap();
+
+        let config = ModprobeConfig {
+            blacklists: vec!["nouveau".to_string()].into_iter().collect(),
+            install_commands: vec![
+                (
+                    "fake".into(),
+                    "modprobe -i fake; /usr/bin/fake-setup $CMDLINE_OPTS; /usr/sbin/fake-helper"
+                        .into(),
+                ),
+                ("other".into(), "/usr/bin/other".into()),
+                ("missing".into(), "notinpath".into()),
+            ],
+            remove_commands: vec![
+                ("fake".into(), "/usr/sbin/fake-helper --stop".into()),
+                ("missing".into(), "/usr/sbin/missing-cleanup".into()),
+            ],
+            ..Default::default()
+        };
+        assert!(config.is_blacklisted(OsStr::new("nouveau")));
+        assert!(!config.is_blacklisted(OsStr::new("fake")));
+
+        let modules: HashSet<Vec<u8>> = vec![b"fake".to_vec(), b"missing".to_vec()]
+            .into_iter()
+            .collect();
+        let pathdirs = [OsString::from("/usr/bin"), OsString::from("/usr/sbin")];
+        assert_eq!(
+            config.install_programs(&modules, &pathdirs, sysroot),
+            [
+                "/usr/sbin/modprobe",
+                "/usr/sbin/fake-helper",
+                "/usr/sbin/missing-cleanup"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_modprobe_d() {
+        let config = ModprobeConfig {
+            blacklists: vec!["nouveau".to_string(), "pcspkr".to_string()]
+                .into_iter()
+                .collect(),
+            aliases: vec![
+                ("eth0".into(), "r8169".into()),
+                ("wlan0".into(), "iwlwifi".into()),
+            ],
+            options: vec![
+                ("nvme_core".into(), "multipath=N".into()),
+                ("iwlwifi".into(), "power_save=0".into()),
+            ],
+            softdeps: vec![("nvme".into(), "pre: nvme_core".into())],
+            ..Default::default()
+        };
+        let modules: HashSet<Vec<u8>> = vec![
+            b"nvme".to_vec(),
+            b"nvme_core".to_vec(),
+            b"r8169".to_vec(),
+            b"nouveau".to_vec(),
+        ]
+        .into_iter()
+        .collect();
+
+        let tmp_dir = TempDir::new().unwrap();
+        config.write(&modules, tmp_dir.path()).unwrap();
+        assert_eq!(
+            fs::read_to_string(tmp_dir.path().join(MODPROBE_CONF)).unwrap(),
+            "# The modprobe.d configuration of the host for the installed modules\n\
+             blacklist nouveau\n\
+             alias eth0 r8169\n\
+             options nvme_core multipath=N\n\
+             softdep nvme pre: nvme_core\n"
+        );
+
+        let tmp_dir = TempDir::new().unwrap();
+        config
+            .write(
+                &vec![b"ext4".to_vec()].into_iter().collect(),
+                tmp_dir.path(),
+            )
+            .unwrap();
+        assert!(!tmp_dir.path().join("etc").exists());
+    }
+
+    #[test]
+    fn test_write_modprobe_d_runs() {
+        let config = ModprobeConfig {
+            aliases: vec![("eth0".into(), "r8169".into())],
+            options: vec![("nvme_core".into(), "multipath=N".into())],
+            ..Default::default()
+        };
+        let tmp_dir = TempDir::new().unwrap();
+        let destroot = tmp_dir.path();
+        let kernel_dir = Path::new("/lib/modules/1.0");
+        let module_dir = destroot.join("lib/modules/1.0/kernel");
+        fs::create_dir_all(&module_dir).unwrap();
+        let run = |module: &str| {
+            fs::write(module_dir.join(module), "").unwrap();
+            config
+                .write(&installed_module_names(kernel_dir, destroot), destroot)
+                .unwrap();
+            fs::read_to_string(destroot.join(MODPROBE_CONF)).unwrap_or_default()
+        };
+
+        // a later run keeps the configuration of the modules of an earlier run
+        assert!(run("nvme-core.ko.xz").ends_with("options nvme_core multipath=N\n"));
+        let conf = run("r8169.ko");
+        assert!(conf.contains("alias eth0 r8169\n"));
+        assert!(conf.contains("options nvme_core multipath=N\n"));
+
+        // the file of an earlier run is removed, when nothing applies anymore
+        fs::remove_dir_all(&module_dir).unwrap();
+        fs::create_dir_all(&module_dir).unwrap();
+        assert_eq!(run("ext4.ko"), "");
+        assert!(!destroot.join(MODPROBE_CONF).exists());
+    }
+}
@@ -0,0 +1,400 @@
+//! The kernel modules for network interfaces of the running system
+//!
+//! The interfaces are looked up in `/sys/class/net` by name, by MAC address or as the
+//! interfaces of the default routes in `/proc/net/route` and `/proc/net/ipv6_route`.
+//! For every interface the driver of its device is used, or the `modalias` of a device
+//! without a loadable driver. The drivers of its PHY, of the buses up the device
+//! hierarchy and of the lower interfaces of bonds, bridges and VLANs are helpers.
+
+use std::collections::BTreeSet;
+use std::convert::Infallible;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::os::unix::prelude::*;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use hashbrown::HashSet;
+
+use super::{attribute, driver_module};
+
+/// How to select a network interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetSelector {
+    /// the interface name, like `eth0`
+    Name(OsString),
+    /// the MAC address, like `52:54:00:12:34:56`
+    Mac(String),
+    /// the interfaces of the IPv4 and IPv6 default routes with the lowest metric
+    DefaultRoute,
+}
+
+impl FromStr for NetSelector {
+    type Err = Infallible;
+
+    /// `default` for the default route, a MAC address or an interface name
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let is_mac = s.split(':').count() == 6
+            && s.split(':')
+                .all(|b| b.len() == 2 && b.bytes().all(|c| c.is_ascii_hexdigit()));
+        Ok(if s == "default" {
+            NetSelector::DefaultRoute
+        } else if is_mac {
+            NetSelector::Mac(s.to_ascii_lowercase())
+        } else {
+            NetSelector::Name(s.into())
+        })
+    }
+}
+
+/// The modules for a set of network interfaces
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NetModules {
+    /// the selected interfaces and their lower interfaces
+    pub interfaces: BTreeSet<OsString>,
+    /// the drivers of the interfaces
+    pub drivers: BTreeSet<OsString>,
+    /// the modaliases of the devices without a loadable driver
+    pub modaliases: BTreeSet<OsString>,
+    /// the drivers of the PHYs and of the buses the devices are connected to
+    pub helpers: BTreeSet<OsString>,
+}
+
+impl NetModules {
+    /// The module names and modaliases to install
+    pub fn modules(&self) -> Vec<OsString> {
+        self.drivers
+            .iter()
+            .chain(self.helpers.iter())
+            .chain(self.modaliases.iter())
+            .cloned()
+            .collect()
+    }
+}
+
+/// Find the modules for the interfaces selected by `selectors`.
+///
+/// The interfaces are looked up in `sysfs` and the routes in `procfs`, normally `/sys`
+/// and `/proc`.
+pub fn net_modules(
+    sysfs: &Path,
+    procfs: &Path,
+    selectors: &[NetSelector],
+) -> Result<NetModules, Box<dyn std::error::Error + 'static + Send + Sync>> {
+    let class_net = sysfs.join("class/net");
+    let mut names = Vec::new();
+
+    for selector in selectors {
+        let found = match selector {
+            NetSelector::Name(name) => {
+                if class_net.join(name).exists() {
+                    vec![name.clone()]
+                } else {
+                    vec![]
+                }
+            }
+            NetSelector::Mac(mac) => fs::read_dir(&class_net)?
+                .filter_map(Result::ok)
+                .filter(|e| {
+                    attribute(&e.path(), "address").map(|a| a.to_ascii_lowercase())
+                        == Some(mac.as_bytes().to_vec())
+                })
+                .map(|e| e.file_name())
+                .collect(),
+            NetSelector::DefaultRoute => default_route_interfaces(procfs),
+        };
+        if found.is_empty() {
+            return Err(format!("No network interface for {:?}", selector).into());
+        }
+        names.extend(found);
+    }
+
+    let mut walker = Walker {
+        class_net,
+        devices: fs::canonicalize(sysfs.join("devices")).unwrap_or_else(|_| sysfs.join("devices")),
+        modules: NetModules::default(),
+        visited: HashSet::new(),
+    };
+    for name in names {
+        walker.add_interface(&name);
+    }
+    Ok(walker.modules)
+}
+
+/// The interfaces of the IPv4 and the IPv6 default route with the lowest metric
+fn default_route_interfaces(procfs: &Path) -> Vec<OsString> {
+    const RTF_UP: u32 = 0x0001;
+    let parse = |s: &[u8], radix| {
+        std::str::from_utf8(s)
+            .ok()
+            .and_then(|s| u32::from_str_radix(s, radix).ok())
+    };
+    let hex = |s: &[u8]| parse(s, 16);
+    let mut interfaces = Vec::new();
+
+    // Iface Destination Gateway Flags RefCnt Use Metric Mask …, the metric is decimal
+    let route = fs::read(procfs.join("net/route")).unwrap_or_default();
+    let ipv4 = route
+        .split(|c| *c == b'\n')
+        .skip(1)
+        .filter_map(|line| {
+            let f = line
+                .split(|c| c.is_ascii_whitespace())
+                .filter(|f| !f.is_empty())
+                .collect::<Vec<_>>();
+            match f.as_slice() {
+                [iface, b"00000000", _, flags, _, _, metric, b"00000000", ..]
+                    if hex(flags)? & RTF_UP != 0 =>
+                {
+                    Some((parse(metric, 10)?, *iface))
+                }
+                _ => None,
+            }
+        })
+        .min();
+
+    // dest dest_plen src src_plen gateway metric refcnt use flags iface
+    let ipv6_route = fs::read(procfs.join("net/ipv6_route")).unwrap_or_default();
+    let ipv6 = ipv6_route
+        .split(|c| *c == b'\n')
+        .filter_map(|line| {
+            let f = line
+                .split(|c| c.is_ascii_whitespace())
+                .filter(|f| !f.is_empty())
+                .collect::<Vec<_>>();
+            match f.as_slice() {
+                [dest, b"00", _, _, _, metric, _, _, flags, iface]
+                    if dest.iter().all(|c| *c == b'0')
+                        && *iface != b"lo"
+                        && hex(flags)? & RTF_UP != 0 =>
+                {
+                    Some((hex(metric)?, *iface))
+                }
+                _ => None,
+            }
+        })
+        .min();
+
+    for (_, iface) in ipv4.into_iter().chain(ipv6) {
+        let iface = OsStr::from_bytes(iface).to_os_string();
+        if !interfaces.contains(&iface) {
+            interfaces.push(iface);
+        }
+    }
+    interfaces
+}
+
+/// The module of a virtual interface by the `DEVTYPE` of its `uevent`
+fn devtype_module(devtype: &[u8]) -> Option<&'static str> {
+    match devtype {
+        b"bond" => Some("bonding"),
+        b"bridge" => Some("bridge"),
+        b"vlan" => Some("8021q"),
+        b"team" => Some("team"),
+        b"macvlan" => Some("macvlan"),
+        b"vxlan" => Some("vxlan"),
+        b"wireguard" => Some("wireguard"),
+        _ => None,
+    }
+}
+
+struct Walker {
+    class_net: PathBuf,
+    /// the root of the device hierarchy
+    devices: PathBuf,
+    modules: NetModules,
+    visited: HashSet<OsString>,
+}
+
+impl Walker {
+    fn add_interface(&mut self, name: &OsStr) {
+        if !self.visited.insert(name.to_os_string()) {
+            return;
+        }
+        self.modules.interfaces.insert(name.to_os_string());
+        let dir = self.class_net.join(name);
+
+        match fs::canonicalize(dir.join("device")) {
+            Ok(device) => {
+                match driver_module(&device) {
+                    Some(module) => {
+                        self.modules.drivers.insert(module);
+                    }
+                    None => {
+                        if let Some(modalias) = attribute(&device, "modalias") {
+                            self.modules.modaliases.insert(OsString::from_vec(modalias));
+                        }
+                    }
+                }
+                let helpers = device
+                    .ancestors()
+                    .skip(1)
+                    .take_while(|d| *d != self.devices)
+                    .filter_map(driver_module)
+                    .collect::<Vec<_>>();
+                self.modules.helpers.extend(helpers);
+            }
+            Err(_) => {
+                let devtype = attribute(&dir, "uevent").and_then(|uevent| {
+                    uevent
+                        .split(|c| *c == b'\n')
+                        .find_map(|l| l.strip_prefix(b"DEVTYPE=").map(<[u8]>::to_vec))
+                });
+                if let Some(module) = devtype.as_deref().and_then(devtype_module) {
+                    self.modules.drivers.insert(module.into());
+                }
+            }
+        }
+
+        if let Ok(phy) = fs::canonicalize(dir.join("phydev")) {
+            if let Some(module) = driver_module(&phy) {
+                self.modules.helpers.insert(module);
+            }
+        }
+
+        let mut lower = fs::read_dir(&dir)
+            .into_iter()
+            .flatten()
+            .filter_map(Result::ok)
+            .filter_map(|e| {
+                e.file_name()
+                    .as_bytes()
+                    .strip_prefix(b"lower_")
+                    .map(|n| OsStr::from_bytes(n).to_os_string())
+            })
+            .collect::<Vec<_>>();
+        lower.sort();
+        for name in lower {
+            self.add_interface(&name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{default_route_interfaces, net_modules, NetSelector};
+    use crate::modules::fixture::{link, write};
+    use std::ffi::OsString;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_net_selector() {
+        assert_eq!(
+            "default".parse::<NetSelector>().unwrap(),
+            NetSelector::DefaultRoute
+        );
+        assert_eq!(
+            "52:54:00:AB:cd:56".parse::<NetSelector>().unwrap(),
+            NetSelector::Mac("52:54:00:ab:cd:56".into())
+        );
+        assert_eq!(
+            "enp0s31f6".parse::<NetSelector>().unwrap(),
+            NetSelector::Name("enp0s31f6".into())
+        );
+    }
+
+    #[test]
+    fn test_net_modules() {
+        let tmp_dir = TempDir::new().unwrap();
+        let sysfs = tmp_dir.path().join("sys");
+        let procfs = tmp_dir.path().join("proc");
+        let devices = sysfs.join("devices");
+
+        let driver =
+            |dir: &Path, module: &str| crate::modules::fixture::driver(&sysfs, dir, module);
+        let interface = |dir: &Path, mac: &str| {
+            write(dir.join("address"), &format!("{}\n", mac));
+            link(dir, sysfs.join("class/net").join(dir.file_name().unwrap()));
+        };
+
+        // a PCI NIC with a PHY on its MDIO bus
+        let pci = devices.join("pci0000:00/0000:00:1c.0/0000:02:00.0");
+        driver(&pci, "r8169");
+        let eth0 = pci.join("net/enp2s0");
+        interface(&eth0, "52:54:00:12:34:56");
+        link(&pci, eth0.join("device"));
+        let phy = pci.join("mdio_bus/r8169-200/r8169-200:00");
+        driver(&phy, "realtek");
+        link(&phy, eth0.join("phydev"));
+
+        // a USB NIC behind an xHCI controller
+        let xhci = devices.join("pci0000:00/0000:00:14.0");
+        driver(&xhci, "xhci_pci");
+        let usb = xhci.join("usb2/2-1/2-1:1.0");
+        driver(&usb, "r8152");
+        let eth1 = usb.join("net/enx00e04c680001");
+        interface(&eth1, "00:e0:4c:68:00:01");
+        link(&usb, eth1.join("device"));
+
+        // a NIC with a builtin driver
+        let virtio = devices.join("pci0000:00/0000:00:03.0/virtio0");
+        write(virtio.join("modalias"), "virtio:d00000001v00001AF4\n");
+        let eth2 = virtio.join("net/eth2");
+        interface(&eth2, "52:54:00:00:00:02");
+        link(&virtio, eth2.join("device"));
+
+        // a bond of the PCI and the USB NIC
+        let bond = devices.join("virtual/net/bond0");
+        interface(&bond, "52:54:00:12:34:56");
+        write(
+            bond.join("uevent"),
+            "DEVTYPE=bond\nINTERFACE=bond0\nIFINDEX=5\n",
+        );
+        link(&eth0, bond.join("lower_enp2s0"));
+        link(&eth1, bond.join("lower_enx00e04c680001"));
+
+        write(
+            procfs.join("net/route"),
+            "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n\
+             eth2\t00000000\t0102A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0\n\
+             bond0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0\n\
+             bond0\t0001A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0\n",
+        );
+        write(
+            procfs.join("net/ipv6_route"),
+            "00000000000000000000000000000000 00 00000000000000000000000000000000 00 \
+             00000000000000000000000000000000 ffffffff 00000001 00000000 00200200       lo\n",
+        );
+
+        let os = |v: &[&str]| v.iter().map(OsString::from).collect();
+        assert_eq!(default_route_interfaces(&procfs), [OsString::from("bond0")]);
+
+        let net = net_modules(&sysfs, &procfs, &[NetSelector::DefaultRoute]).unwrap();
+        assert_eq!(net.interfaces, os(&["bond0", "enp2s0", "enx00e04c680001"]));
+        assert_eq!(net.drivers, os(&["bonding", "r8152", "r8169"]));
+        assert_eq!(net.helpers, os(&["realtek", "xhci_pci"]));
+        assert!(net.modaliases.is_empty());
+
+        let net = net_modules(
+            &sysfs,
+            &procfs,
+            &[
+                NetSelector::Name("eth2".into()),
+                "00:E0:4C:68:00:01".parse().unwrap(),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            net.modules(),
+            ["r8152", "xhci_pci", "virtio:d00000001v00001AF4"]
+        );
+
+        assert!(net_modules(&sysfs, &procfs, &[NetSelector::Name("wlan0".into())]).is_err());
+        assert!(net_modules(
+            &sysfs,
+            &tmp_dir.path().join("none"),
+            &[NetSelector::DefaultRoute]
+        )
+        .is_err());
+
+        // the decimal metric of the IPv4 routes may exceed 8 hexadecimal digits
+        write(
+            procfs.join("net/route"),
+            "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n\
+             eth2\t00000000\t0102A8C0\t0003\t0\t0\t4000000000\t00000000\t0\t0\t0\n\
+             bond0\t00000000\t0101A8C0\t0003\t0\t0\t4100000000\t00000000\t0\t0\t0\n",
+        );
+        assert_eq!(default_route_interfaces(&procfs), [OsString::from("eth2")]);
+    }
+}
@@ -0,0 +1,482 @@
+//! The kernel modules for the block device stack of a mount point or block device
+//!
+//! The mount is looked up in `/proc/self/mountinfo`, then the block devices are walked
+//! in sysfs: down the `slaves` of device-mapper and MD RAID devices to the partitions
+//! and disks, and from the disks up the device hierarchy to their controllers. For a
+//! block device given directly, the devices stacked on top of it in `holders` are
+//! walked, too.
+
+use std::collections::BTreeSet;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::os::unix::prelude::*;
+use std::path::{Path, PathBuf};
+
+use hashbrown::HashSet;
+
+use super::{attribute, driver_module};
+
+/// The modules needed to mount a file system or to access a block device
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StorageModules {
+    /// the file system type of the mount
+    pub filesystem: Option<OsString>,
+    /// the modules of the device-mapper targets, MD RAID personalities and virtual
+    /// block devices
+    pub targets: BTreeSet<OsString>,
+    /// the drivers of the disks and their controllers
+    pub drivers: BTreeSet<OsString>,
+    /// the names of all block devices of the stack
+    pub devices: BTreeSet<OsString>,
+}
+
+impl StorageModules {
+    /// The module names and aliases to install, the file system as `fs-<type>` alias,
+    /// because the module of a file system type can have another name.
+    pub fn modules(&self) -> Vec<OsString> {
+        let mut modules = Vec::new();
+        if let Some(fs) = &self.filesystem {
+            let mut alias = OsString::from("fs-");
+            alias.push(fs);
+            modules.push(alias);
+        }
+        modules.extend(self.targets.iter().cloned());
+        modules.extend(self.drivers.iter().cloned());
+        modules
+    }
+}
+
+/// Find the modules for `target`, a mount point, a path on a mount or a block device.
+///
+/// `target` may be relative or contain symlinks, it has to exist.
+/// `mountinfo` is the content of `/proc/self/mountinfo`, the block devices are
+/// looked up in `sysfs`, normally `/sys`.
+pub fn storage_modules(
+    sysfs: &Path,
+    mountinfo: &[u8],
+    target: &Path,
+) -> Result<StorageModules, Box<dyn std::error::Error + 'static + Send + Sync>> {
+    let mut modules = StorageModules::default();
+
+    // the mount points in mountinfo are canonical
+    let target = &fs::canonicalize(target).map_err(|e| format!("Storage {:?}: {}", target, e))?;
+    let (dev, up) = match fs::metadata(target) {
+        Ok(m) if m.file_type().is_block_device() => (Some(dev_numbers(m.rdev())), true),
+        _ => {
+            let mount = find_mount(mountinfo, target)
+                .ok_or_else(|| format!("No mount found for {:?}", target))?;
+            modules.filesystem = Some(mount.fstype.clone());
+            (mount_device(&mount), false)
+        }
+    };
+
+    // file systems without a block device, like tmpfs or nfs
+    let (major, minor) = match dev {
+        Some(dev) => dev,
+        None => return Ok(modules),
+    };
+
+    let dev_dir = fs::canonicalize(sysfs.join(format!("dev/block/{}:{}", major, minor)))
+        .map_err(|e| format!("Block device {}:{} in {:?}: {}", major, minor, sysfs, e))?;
+
+    let mut walker = Walker {
+        sysfs,
+        modules: &mut modules,
+        visited: HashSet::new(),
+    };
+    if up {
+        walker.walk_holders(&dev_dir);
+    }
+    walker.walk(&dev_dir);
+
+    Ok(modules)
+}
+
+/// One line of `mountinfo`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Mount {
+    dev: (u32, u32),
+    mount_point: PathBuf,
+    fstype: OsString,
+    source: OsString,
+}
+
+/// The mount of `path`, the last of the mounts with the longest mount point
+fn find_mount(mountinfo: &[u8], path: &Path) -> Option<Mount> {
+    let mut found: Option<Mount> = None;
+    for mount in mountinfo.split(|c| *c == b'\n').filter_map(parse_mountinfo) {
+        if !path.starts_with(&mount.mount_point) {
+            continue;
+        }
+        match &found {
+            Some(f) if f.mount_point.as_os_str().len() > mount.mount_point.as_os_str().len() => {}
+            _ => found = Some(mount),
+        }
+    }
+    found
+}
+
+/// Parse `<id> <parent> <major>:<minor> <root> <mount point> <options> [<optional>…]
+/// - <fstype> <source> <super options>`
+fn parse_mountinfo(line: &[u8]) -> Option<Mount> {
+    let fields = line.split(|c| *c == b' ').collect::<Vec<_>>();
+    let separator = fields.iter().position(|f| *f == b"-")?;
+    if separator < 6 {
+        return None;
+    }
+
+    let mut dev = fields[2].split(|c| *c == b':').map(|n| {
+        std::str::from_utf8(n)
+            .ok()
+            .and_then(|n| n.parse::<u32>().ok())
+    });
+    let dev = (dev.next()??, dev.next()??);
+
+    Some(Mount {
+        dev,
+        mount_point: PathBuf::from(OsString::from_vec(unescape(fields[4]))),
+        fstype: OsString::from_vec(fields.get(separator + 1)?.to_vec()),
+        source: OsString::from_vec(unescape(fields.get(separator + 2)?)),
+    })
+}
+
+/// Undo the octal escapes of space, tab, newline and backslash in `mountinfo`
+fn unescape(field: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(field.len());
+    let mut i = 0;
+    while i < field.len() {
+        let code = field
+            .get(i + 1..i + 4)
+            .filter(|_| field[i] == b'\\')
+            .and_then(|o| std::str::from_utf8(o).ok())
+            .and_then(|o| u8::from_str_radix(o, 8).ok());
+        match code {
+            Some(c) => {
+                out.push(c);
+                i += 4;
+            }
+            None => {
+                out.push(field[i]);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// The block device of `mount`.
+///
+/// File systems spanning several devices, like btrfs, report an anonymous device
+/// number, then the source device is used.
+fn mount_device(mount: &Mount) -> Option<(u32, u32)> {
+    if mount.dev.0 != 0 {
+        return Some(mount.dev);
+    }
+    match fs::metadata(&mount.source) {
+        Ok(m) if m.file_type().is_block_device() => Some(dev_numbers(m.rdev())),
+        _ => None,
+    }
+}
+
+/// Split a `dev_t` in major and minor number, like `gnu_dev_major` and `gnu_dev_minor`
+fn dev_numbers(dev: u64) -> (u32, u32) {
+    let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff);
+    let minor = (dev & 0xff) | ((dev >> 12) & !0xff);
+    (major as u32, minor as u32)
+}
+
+/// The module of a device-mapper device by the prefix and suffix of its `dm/uuid`
+///
+/// `dm_mod` itself, with the linear and striped targets, is added for every device.
+fn dm_target(uuid: &[u8], name: &[u8]) -> Option<&'static str> {
+    const PREFIXES: &[(&[u8], &str)] = &[
+        (b"CRYPT-VERITY-", "dm_verity"),
+        (b"CRYPT-INTEGRITY-", "dm_integrity"),
+        (b"CRYPT-SUBDEV-", "dm_integrity"),
+        (b"CRYPT-", "dm_crypt"),
+        (b"mpath-", "dm_multipath"),
+    ];
+    const LVM_SUFFIXES: &[(&[u8], &str)] = &[
+        (b"-tpool", "dm_thin_pool"),
+        (b"-pool", "dm_thin_pool"),
+        (b"-cpool", "dm_cache"),
+        (b"-cdata", "dm_cache"),
+        (b"-real", "dm_snapshot"),
+        (b"-cow", "dm_snapshot"),
+        (b"-vdo", "dm_vdo"),
+    ];
+
+    if let Some((_, module)) = PREFIXES.iter().find(|(p, _)| uuid.starts_with(p)) {
+        return Some(module);
+    }
+    if uuid.starts_with(b"LVM-") {
+        if let Some((_, module)) = LVM_SUFFIXES.iter().find(|(s, _)| uuid.ends_with(s)) {
+            return Some(module);
+        }
+        // the sub volumes of raid logical volumes
+        if name.windows(8).any(|w| w == b"_rimage_") {
+            return Some("dm_raid");
+        }
+        if name.ends_with(b"-tpool") || name.ends_with(b"_tdata") {
+            return Some("dm_thin_pool");
+        }
+    }
+    None
+}
+
+/// The MD RAID personality of `md/level`
+fn md_personality(level: &[u8]) -> Option<&'static str> {
+    match level {
+        b"raid0" => Some("raid0"),
+        b"raid1" => Some("raid1"),
+        b"raid4" | b"raid5" | b"raid6" => Some("raid456"),
+        b"raid10" => Some("raid10"),
+        b"linear" => Some("linear"),
+        b"multipath" => Some("multipath"),
+        _ => None,
+    }
+}
+
+/// The module of a virtual block device by its name without the number
+fn virtual_block_module(name: &[u8]) -> Option<&'static str> {
+    match name.split(|c| c.is_ascii_digit()).next()? {
+        b"loop" => Some("loop"),
+        b"nbd" => Some("nbd"),
+        b"zram" => Some("zram"),
+        b"ram" => Some("brd"),
+        b"rbd" => Some("rbd"),
+        _ => None,
+    }
+}
+
+struct Walker<'a> {
+    sysfs: &'a Path,
+    modules: &'a mut StorageModules,
+    visited: HashSet<PathBuf>,
+}
+
+impl Walker<'_> {
+    /// The device directories of the block devices named in `dir`, like `slaves`
+    fn block_devices(&self, dir: &Path) -> Vec<PathBuf> {
+        let mut devices = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(Result::ok)
+            .filter_map(|e| {
+                fs::canonicalize(self.sysfs.join("class/block").join(e.file_name())).ok()
+            })
+            .collect::<Vec<_>>();
+        devices.sort();
+        devices
+    }
+
+    /// Add the block device `dev_dir` and everything below it.
+    fn walk(&mut self, dev_dir: &Path) {
+        if !self.visited.insert(dev_dir.to_path_buf()) {
+            return;
+        }
+        self.add_layer(dev_dir);
+
+        let slaves = self.block_devices(&dev_dir.join("slaves"));
+        if slaves.is_empty() {
+            self.add_drivers(dev_dir);
+        }
+        for slave in slaves {
+            self.walk(&slave);
+        }
+    }
+
+    /// Add the modules of the devices stacked on top of `dev_dir`.
+    fn walk_holders(&mut self, dev_dir: &Path) {
+        for holder in self.block_devices(&dev_dir.join("holders")) {
+            if self.visited.insert(holder.clone()) {
+                self.add_layer(&holder);
+                self.walk_holders(&holder);
+            }
+        }
+    }
+
+    /// Add the name and the device-mapper, MD RAID or virtual device module of `dev_dir`.
+    fn add_layer(&mut self, dev_dir: &Path) {
+        let name = dev_dir.file_name().unwrap_or_default().as_bytes();
+        self.modules
+            .devices
+            .insert(OsStr::from_bytes(name).to_os_string());
+
+        if let Some(uuid) = attribute(dev_dir, "dm/uuid") {
+            let dm_name = attribute(dev_dir, "dm/name").unwrap_or_default();
+            self.modules.targets.insert("dm_mod".into());
+            if let Some(module) = dm_target(&uuid, &dm_name) {
+                self.modules.targets.insert(module.into());
+            }
+        } else if let Some(level) = attribute(dev_dir, "md/level") {
+            self.modules.targets.insert("md_mod".into());
+            if let Some(module) = md_personality(&level) {
+                self.modules.targets.insert(module.into());
+            }
+        } else if dev_dir.parent().map(|p| p.ends_with("virtual/block")) == Some(true) {
+            if let Some(module) = virtual_block_module(name) {
+                self.modules.targets.insert(module.into());
+            }
+        }
+    }
+
+    /// Add the driver modules of the devices from `dev_dir` up to the root of the
+    /// device hierarchy.
+    fn add_drivers(&mut self, dev_dir: &Path) {
+        let devices = fs::canonicalize(self.sysfs.join("devices"))
+            .unwrap_or_else(|_| self.sysfs.join("devices"));
+
+        for dir in dev_dir.ancestors().take_while(|d| *d != devices) {
+            if let Some(module) = driver_module(dir) {
+                self.modules.drivers.insert(module);
+            }
+
+            // the controllers of a native NVMe multipath device are links in its subsystem
+            if dir
+                .file_name()
+                .map(|n| n.as_bytes().starts_with(b"nvme-subsys"))
+                == Some(true)
+            {
+                let controllers = fs::read_dir(dir)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Result::ok)
+                    .filter(|e| e.file_type().map(|t| t.is_symlink()).unwrap_or(false))
+                    .filter_map(|e| fs::canonicalize(e.path()).ok())
+                    .collect::<Vec<_>>();
+                for controller in controllers {
+                    if self.visited.insert(controller.clone()) {
+                        self.add_drivers(&controller);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{find_mount, storage_modules, unescape, StorageModules};
+    use crate::modules::fixture::{link, mkdir, write};
+    use std::ffi::OsString;
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    const MOUNTINFO: &str = "\
+22 1 253:1 / / rw,relatime shared:1 - xfs /dev/mapper/vg-root rw,attr2\n\
+23 22 0:21 / /proc rw,nosuid shared:12 - proc proc rw\n\
+24 22 259:2 / /boot rw,relatime shared:2 - ext4 /dev/nvme0n1p2 rw\n\
+25 22 0:40 / /mnt/my\\040data rw - tmpfs tmpfs rw\n";
+
+    #[test]
+    fn test_find_mount() {
+        let mount = find_mount(MOUNTINFO.as_bytes(), Path::new("/boot/efi")).unwrap();
+        assert_eq!(mount.dev, (259, 2));
+        assert_eq!(mount.fstype, "ext4");
+        let mount = find_mount(MOUNTINFO.as_bytes(), Path::new("/usr")).unwrap();
+        assert_eq!(mount.mount_point, Path::new("/"));
+        assert_eq!(mount.source, "/dev/mapper/vg-root");
+        let mount = find_mount(MOUNTINFO.as_bytes(), Path::new("/mnt/my data")).unwrap();
+        assert_eq!(mount.fstype, "tmpfs");
+        assert!(find_mount(b"garbage\n", Path::new("/")).is_none());
+        assert_eq!(unescape(b"a\\134b\\011"), b"a\\b\t");
+    }
+
+    #[test]
+    fn test_storage_modules() {
+        let tmp_dir = TempDir::new().unwrap();
+        let sysfs = tmp_dir.path().join("sys");
+        let devices = sysfs.join("devices");
+
+        let driver =
+            |dir: &Path, module: &str| crate::modules::fixture::driver(&sysfs, dir, module);
+        let block = |dir: &Path, dev: &str| {
+            mkdir(dir);
+            let name = dir.file_name().unwrap();
+            link(dir, sysfs.join("class/block").join(name));
+            link(dir, sysfs.join("dev/block").join(dev));
+        };
+        let slave = |holder: &Path, slave: &Path| {
+            let (h, s) = (holder.file_name().unwrap(), slave.file_name().unwrap());
+            link(slave, holder.join("slaves").join(s));
+            link(holder, slave.join("holders").join(h));
+        };
+
+        // nvme0n1p3 <- md127 (raid1, with sda1) <- dm-0 (crypt) <- dm-1 (lvm)
+        let pci = devices.join("pci0000:00");
+        driver(&pci.join("0000:00:1d.0"), "nvme");
+        let nvme = pci.join("0000:00:1d.0/nvme/nvme0/nvme0n1");
+        block(&nvme, "259:0");
+        let nvme_part = nvme.join("nvme0n1p3");
+        block(&nvme_part, "259:3");
+        write(nvme_part.join("partition"), "3\n");
+
+        driver(&pci.join("0000:00:17.0"), "ahci");
+        let scsi = pci.join("0000:00:17.0/ata1/host0/target0:0:0/0:0:0:0");
+        driver(&scsi, "sd_mod");
+        let sda = scsi.join("block/sda");
+        block(&sda, "8:0");
+        let sda1 = sda.join("sda1");
+        block(&sda1, "8:1");
+
+        let md = devices.join("virtual/block/md127");
+        block(&md, "9:127");
+        write(md.join("md/level"), "raid1\n");
+        slave(&md, &nvme_part);
+        slave(&md, &sda1);
+
+        let crypt = devices.join("virtual/block/dm-0");
+        block(&crypt, "253:0");
+        write(crypt.join("dm/uuid"), "CRYPT-LUKS2-0123-luks-0123\n");
+        write(crypt.join("dm/name"), "luks-0123\n");
+        slave(&crypt, &md);
+
+        let lv = devices.join("virtual/block/dm-1");
+        block(&lv, "253:1");
+        write(lv.join("dm/uuid"), "LVM-abcdef\n");
+        write(lv.join("dm/name"), "vg-root\n");
+        slave(&lv, &crypt);
+
+        let loop0 = devices.join("virtual/block/loop0");
+        block(&loop0, "7:0");
+
+        let os = |v: &[&str]| v.iter().map(OsString::from).collect();
+        let root = storage_modules(&sysfs, MOUNTINFO.as_bytes(), Path::new("/")).unwrap();
+        assert_eq!(
+            root,
+            StorageModules {
+                filesystem: Some("xfs".into()),
+                targets: os(&["dm_crypt", "dm_mod", "md_mod", "raid1"]),
+                drivers: os(&["ahci", "nvme", "sd_mod"]),
+                devices: os(&["dm-0", "dm-1", "md127", "nvme0n1p3", "sda1"]),
+            }
+        );
+        assert_eq!(
+            root.modules(),
+            ["fs-xfs", "dm_crypt", "dm_mod", "md_mod", "raid1", "ahci", "nvme", "sd_mod"]
+        );
+
+        let proc = storage_modules(&sysfs, MOUNTINFO.as_bytes(), Path::new("/proc")).unwrap();
+        assert_eq!(proc.modules(), ["fs-proc"]);
+
+        // the target is canonicalized before looking up its mount
+        let mnt = fs::canonicalize(tmp_dir.path()).unwrap().join("live");
+        mkdir(mnt.join("dir"));
+        link(mnt.join("dir"), tmp_dir.path().join("link"));
+        let loop_info = format!("30 1 7:0 / {} rw - squashfs /dev/loop0 ro\n", mnt.display());
+        let live = storage_modules(
+            &sysfs,
+            loop_info.as_bytes(),
+            &tmp_dir.path().join("link/../dir"),
+        )
+        .unwrap();
+        assert_eq!(live.modules(), ["fs-squashfs", "loop"]);
+        assert!(storage_modules(&sysfs, loop_info.as_bytes(), &mnt.join("missing")).is_err());
+
+        // no device 259:2 in sysfs
+        assert!(storage_modules(&sysfs, MOUNTINFO.as_bytes(), Path::new("/boot")).is_err());
+        assert!(storage_modules(&sysfs, b"", Path::new("/")).is_err());
+    }
+}
@@ -0,0 +1,273 @@
+//! Attribution of the installed bytes to the requested files and modules
+//!
+//! Every object reachable from a root of the [`DependencyGraph`] is counted for that
+//! root. Objects reachable from only one root are its exclusive bytes, objects pulled
+//! in by several roots are shared bytes of each of them.
+//!
+//! The graph only holds the objects resolved by this run. Files installed in the
+//! destination root by earlier runs count for the total and the largest files,
+//! but for no root.
+
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use hashbrown::{HashMap, HashSet};
+use walkdir::WalkDir;
+
+use crate::file::in_root;
+use crate::graph::DependencyGraph;
+
+/// The bytes attributed to one root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootSize {
+    pub root: OsString,
+    /// bytes of the objects only this root needs
+    pub exclusive: u64,
+    /// bytes of the objects other roots need, too
+    pub shared: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SizeReport {
+    /// bytes of all objects in the graph and of the other files in the destination root
+    pub total: u64,
+    pub files: usize,
+    /// the roots by exclusive size, largest first
+    pub roots: Vec<RootSize>,
+    /// the largest objects, largest first
+    pub largest: Vec<(OsString, u64)>,
+}
+
+impl SizeReport {
+    /// Account the objects of `graph` with their installed size in `destroot`,
+    /// which is smaller than the source file for stripped objects.
+    ///
+    /// Objects which were not installed, like unresolved firmware names, count 0 bytes.
+    /// The other regular files of `destroot` are added to the total.
+    pub fn new(graph: &DependencyGraph, destroot: &Path, largest: usize) -> SizeReport {
+        let mut inodes = HashSet::new();
+        let sizes = graph
+            .nodes()
+            .into_iter()
+            .map(|n| {
+                let len = match fs::metadata(in_root(destroot, Path::new(n))) {
+                    Ok(m) => {
+                        inodes.insert((m.dev(), m.ino()));
+                        m.len()
+                    }
+                    Err(_) => 0,
+                };
+                (n.clone(), len)
+            })
+            .collect::<HashMap<_, _>>();
+
+        // files of earlier runs, which are not reachable by a path of the graph
+        let earlier = WalkDir::new(destroot)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|e| e.file_type().is_file())
+            .filter_map(|e| {
+                let m = e.metadata().ok()?;
+                if !inodes.insert((m.dev(), m.ino())) {
+                    return None;
+                }
+                let path = Path::new("/").join(e.path().strip_prefix(destroot).ok()?);
+                Some((path.into_os_string(), m.len()))
+            })
+            .collect::<Vec<_>>();
+
+        let reachable = graph
+            .roots
+            .iter()
+            .map(|root| {
+                let mut seen = HashSet::new();
+                let mut stack = vec![root];
+                while let Some(n) = stack.pop() {
+                    if seen.insert(n) {
+                        if let Some(to) = graph.edges.get(n) {
+                            stack.extend(to.iter().map(|(t, _)| t));
+                        }
+                    }
+                }
+                (root, seen)
+            })
+            .collect::<Vec<_>>();
+
+        let mut needed_by = HashMap::<&OsString, usize>::new();
+        for (_, seen) in reachable.iter() {
+            for n in seen.iter() {
+                *needed_by.entry(*n).or_default() += 1;
+            }
+        }
+
+        let mut roots = reachable
+            .iter()
+            .map(|(root, seen)| {
+                let (mut exclusive, mut shared) = (0, 0);
+                for n in seen.iter() {
+                    if needed_by[n] > 1 {
+                        shared += sizes[*n];
+                    } else {
+                        exclusive += sizes[*n];
+                    }
+                }
+                RootSize {
+                    root: (*root).clone(),
+                    exclusive,
+                    shared,
+                }
+            })
+            .collect::<Vec<_>>();
+        roots.sort_by(|a, b| b.exclusive.cmp(&a.exclusive).then(a.root.cmp(&b.root)));
+
+        let mut all = sizes.into_iter().chain(earlier).collect::<Vec<_>>();
+        all.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        SizeReport {
+            total: all.iter().map(|(_, s)| s).sum(),
+            files: all.len(),
+            roots,
+            largest: all.into_iter().take(largest).collect(),
+        }
+    }
+}
+
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} in {} files", HumanSize(self.total), self.files)?;
+        writeln!(f, "{:>10} {:>10}  item", "exclusive", "shared")?;
+        for r in self.roots.iter() {
+            writeln!(
+                f,
+                "{:>10} {:>10}  {}",
+                HumanSize(r.exclusive),
+                HumanSize(r.shared),
+                r.root.to_string_lossy()
+            )?;
+        }
+        writeln!(f, "largest files:")?;
+        for (path, size) in self.largest.iter() {
+            writeln!(f, "{:>10}  {}", HumanSize(*size), path.to_string_lossy())?;
+        }
+        Ok(())
+    }
+}
+
+/// A byte count formatted with a binary unit
+pub struct HumanSize(pub u64);
+
+impl fmt::Display for HumanSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+        if self.0 < 1024 {
+            return f.pad(&format!("{} B", self.0));
+        }
+        let mut size = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+        f.pad(&format!("{:.1} {}", size, UNITS[unit]))
+    }
+}
+
+/// Parse a byte count with an optional `K`, `M` or `G` suffix for powers of 1024.
+pub fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (num, shift) = match s.as_bytes().last()? {
+        b'k' | b'K' => (&s[..s.len() - 1], 10),
+        b'm' | b'M' => (&s[..s.len() - 1], 20),
+        b'g' | b'G' => (&s[..s.len() - 1], 30),
+        _ => (s, 0),
+    };
+    num.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_size, HumanSize, SizeReport};
+    use crate::graph::{DependencyGraph, EdgeKind};
+    use std::ffi::OsStr;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("64K"), Some(64 << 10));
+        assert_eq!(parse_size("100m"), Some(100 << 20));
+        assert_eq!(parse_size("2G"), Some(2 << 30));
+        assert_eq!(parse_size("M"), None);
+        assert_eq!(parse_size("1.5M"), None);
+        assert_eq!(HumanSize(1000).to_string(), "1000 B");
+        assert_eq!(HumanSize(1536).to_string(), "1.5 KiB");
+        assert_eq!(format!("{:>8}", HumanSize(3 << 20)), " 3.0 MiB");
+    }
+
+    #[test]
+    fn test_size_report() {
+        let tmp_dir = TempDir::new().unwrap();
+        let destroot = tmp_dir.path();
+        for (name, len) in [("a", 100), ("b", 200), ("liba", 10), ("libc", 1000)] {
+            fs::write(destroot.join(name), vec![0; len]).unwrap();
+        }
+
+        let os = OsStr::new;
+        let mut g = DependencyGraph::default();
+        g.add_root(os("/a"));
+        g.add_root(os("/b"));
+        g.add_edge(os("/a"), os("/liba"), EdgeKind::Needed);
+        g.add_edge(os("/liba"), os("/libc"), EdgeKind::Needed);
+        g.add_edge(os("/b"), os("/libc"), EdgeKind::Needed);
+        g.add_edge(os("/b"), os("missing.fw"), EdgeKind::Firmware);
+
+        let report = SizeReport::new(&g, destroot, 2);
+        assert_eq!(report.total, 1310);
+        assert_eq!(report.files, 5);
+        assert_eq!(report.roots.len(), 2);
+        assert_eq!(report.roots[0].root, "/b");
+        assert_eq!(
+            (report.roots[0].exclusive, report.roots[0].shared),
+            (200, 1000)
+        );
+        assert_eq!(
+            (report.roots[1].exclusive, report.roots[1].shared),
+            (110, 1000)
+        );
+        assert_eq!(
+            report.largest,
+            vec![("/libc".into(), 1000), ("/b".into(), 200)]
+        );
+    }
+
+    #[test]
+    fn test_size_report_earlier_runs() {
+        let tmp_dir = TempDir::new().unwrap();
+        let destroot = tmp_dir.path();
+        fs::create_dir(destroot.join("lib")).unwrap();
+        fs::write(destroot.join("a"), vec![0; 100]).unwrap();
+        fs::write(destroot.join("lib/liba"), vec![0; 10]).unwrap();
+        fs::write(destroot.join("lib/libold"), vec![0; 5000]).unwrap();
+        // the graph reaches liba through a symlinked directory
+        std::os::unix::fs::symlink("lib", destroot.join("lib64")).unwrap();
+
+        let os = OsStr::new;
+        let mut g = DependencyGraph::default();
+        g.add_root(os("/a"));
+        g.add_edge(os("/a"), os("/lib64/liba"), EdgeKind::Needed);
+
+        let report = SizeReport::new(&g, destroot, 1);
+        assert_eq!(report.total, 5110);
+        assert_eq!(report.files, 3);
+        assert_eq!(report.roots.len(), 1);
+        assert_eq!(
+            (report.roots[0].exclusive, report.roots[0].shared),
+            (110, 0)
+        );
+        assert_eq!(report.largest, vec![("/lib/libold".into(), 5000)]);
+    }
+}